@@ -1,14 +1,88 @@
+use std::{
+    any::{type_name, Any},
+    fmt,
+    future::{poll_fn, Future},
+    panic::{self, AssertUnwindSafe},
+    pin::pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::Duration,
+};
+
 use async_trait::async_trait;
-use tokio::sync::mpsc::{self, Receiver};
+use tokio::{
+    sync::{oneshot, Mutex, Notify},
+    task::JoinHandle,
+};
+use tracing::Instrument;
+
+pub use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::mailbox::{mailbox, MailboxReceiver, MailboxSender, Prioritized, WeakMailboxSender};
+pub use crate::mailbox::{MailboxConfig, Overflow, Priority};
+use crate::registry::{Metrics, Registration};
+#[cfg(test)]
+use crate::sim::{self, Pending};
+use crate::supervisor::FailureReason;
+use crate::trace::{self, Trace};
+
+/// Message trait
+/// Anything that can be sent to an actor. `Response` is what the handler answers with, events
+/// that expect no answer use `()`.
+pub trait Message: Send + 'static {
+    type Response: Send + 'static;
 
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+    /// The lane this message is queued in when the actor's mailbox is prioritized.
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    /// Which worker of a `Router` using `Routing::KeyHash` gets this message, for instance a hash
+    /// of the id it concerns. Messages without a key are routed round robin.
+    fn routing_key(&self) -> Option<u64> {
+        None
+    }
+
+    /// How long the handler may take over this message, overriding `Actor::deadline`.
+    fn deadline(&self) -> Option<Duration> {
+        None
+    }
+}
 
 /// Actor trait
-/// This defines the Actor that handles messages.
+/// This defines the Actor. The messages it accepts are declared by implementing `Handler` once
+/// per message type.
+#[async_trait]
+pub trait Actor: Send + Sized + 'static {
+    /// How long any handler of this actor may take. A handler still running at its deadline is
+    /// dropped, its asker gets `DeadlineExceeded` and the failure is reported like any other, so
+    /// a supervisor restarts the actor. Only handlers that await can be cut short, and work
+    /// handed to `blocking` carries on in the background.
+    fn deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called once before the first message is handled.
+    async fn started(&mut self, _ctx: &mut Context<Self>) {}
+
+    /// Called once the actor has been told to stop or every sender has gone. The mailbox no longer
+    /// accepts messages but the ones already queued have not been handled yet.
+    async fn stopping(&mut self, _ctx: &mut Context<Self>) {}
+
+    /// Called after the remaining messages have been handled and child actors have stopped. This
+    /// is the last chance to flush state or clear secrets before the actor is dropped.
+    async fn stopped(&mut self, _ctx: &mut Context<Self>) {}
+}
+
+/// Handler trait
+/// Handles one message type for an actor. An actor can implement this for as many message types
+/// as it likes and its `Addr` accepts all of them.
 #[async_trait]
-pub trait Actor<M: Send + 'static>: Send + 'static {
-    async fn handle_message(&mut self, msg: M) -> Result<()>;
+pub trait Handler<M: Message>: Actor {
+    async fn handle_message(&mut self, msg: M, ctx: &mut Context<Self>) -> Result<M::Response>;
 }
 
 /// ActorHandle trait
@@ -17,28 +91,1077 @@ pub trait Actor<M: Send + 'static>: Send + 'static {
 #[async_trait]
 pub trait ActorSender<M> {
     async fn send(&self, msg: M) -> Result<()>;
+
+    /// Whether messages can still be sent. `false` means they never can be again.
+    fn is_alive(&self) -> bool {
+        true
+    }
+}
+
+/// Envelope
+/// A message on its way to an actor of type `A` together with where its response goes and the
+/// trace linking it to the message that caused it.
+#[async_trait]
+pub(crate) trait Envelope<A>: Send {
+    fn priority(&self) -> Priority;
+
+    /// Handle the message. A panic in the handler is caught and returned as a failure so it
+    /// cannot take the actor's task down with it. Failures are logged in the handler's span.
+    async fn handle(
+        self: Box<Self>,
+        actor: &mut A,
+        ctx: &mut Context<A>,
+    ) -> std::result::Result<(), FailureReason>;
+}
+
+impl<A> Prioritized for Box<dyn Envelope<A>> {
+    fn priority(&self) -> Priority {
+        Envelope::priority(self.as_ref())
+    }
+}
+
+type Reply<M> = oneshot::Sender<Result<<M as Message>::Response>>;
+
+pub(crate) struct MessageEnvelope<M: Message> {
+    msg: M,
+    reply: Option<Reply<M>>,
+    trace: Trace,
+    #[cfg(test)]
+    pending: Option<Pending>,
+}
+
+impl<M: Message> MessageEnvelope<M> {
+    pub(crate) fn boxed<A: Handler<M>>(msg: M, reply: Option<Reply<M>>) -> Box<dyn Envelope<A>> {
+        Box::new(Self {
+            msg,
+            reply,
+            trace: Trace::new(),
+            #[cfg(test)]
+            pending: sim::track(),
+        })
+    }
+}
+
+#[async_trait]
+impl<A, M> Envelope<A> for MessageEnvelope<M>
+where
+    A: Handler<M>,
+    M: Message,
+{
+    fn priority(&self) -> Priority {
+        self.msg.priority()
+    }
+
+    async fn handle(
+        self: Box<Self>,
+        actor: &mut A,
+        ctx: &mut Context<A>,
+    ) -> std::result::Result<(), FailureReason> {
+        // A simulation counts the message as pending until the handler is done with it
+        let Self {
+            msg,
+            reply,
+            trace,
+            #[cfg(test)]
+                pending: _pending,
+        } = *self;
+        let span = trace.span(type_name::<A>(), type_name::<M>());
+        let deadline = msg.deadline().or_else(|| actor.deadline());
+        let started = ctx.metrics().handling();
+        let handled = trace::scope(trace, catch_unwind(actor.handle_message(msg, ctx)));
+        let handled = async {
+            match deadline {
+                Some(deadline) => tokio::time::timeout(deadline, handled)
+                    .await
+                    .map_err(|_| deadline),
+                None => Ok(handled.await),
+            }
+        };
+        let result = match handled.instrument(span.clone()).await {
+            Err(deadline) => {
+                if let Some(reply) = reply {
+                    let _ = reply.send(Err(Error::DeadlineExceeded(deadline)));
+                }
+                Err(FailureReason::Deadline(deadline))
+            }
+            Ok(Ok(Ok(response))) => {
+                if let Some(reply) = reply {
+                    let _ = reply.send(Ok(response));
+                }
+                Ok(())
+            }
+            Ok(Ok(Err(e))) => {
+                if let Some(reply) = reply {
+                    let _ = reply.send(Err(e.clone()));
+                }
+                Err(FailureReason::Error(e))
+            }
+            Ok(Err(payload)) => {
+                let message = panic_message(payload);
+                if let Some(reply) = reply {
+                    let _ = reply.send(Err(Error::Panicked(message.clone())));
+                }
+                Err(FailureReason::Panic(message))
+            }
+        };
+        ctx.metrics().handled(started, result.is_ok());
+        if let Err(reason) = &result {
+            // Reported in the handler's span so it carries the message's trace
+            span.in_scope(|| tracing::error!(?reason, "handler failed"));
+        }
+        result
+    }
+}
+
+/// Run `future` to completion, returning the panic payload instead if polling it panics.
+async fn catch_unwind<F: Future>(future: F) -> std::thread::Result<F::Output> {
+    let mut future = pin!(future);
+    poll_fn(
+        |cx| match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        },
+    )
+    .await
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+pub(crate) type Mailbox<A> = MailboxReceiver<Box<dyn Envelope<A>>>;
+
+/// Lifecycle
+/// Stops a running actor and waits for its task whatever the actor's type.
+#[derive(Debug, Clone)]
+pub(crate) struct Lifecycle {
+    stop: Arc<Notify>,
+    join: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl Lifecycle {
+    pub(crate) fn new() -> Self {
+        Self {
+            stop: Arc::new(Notify::new()),
+            join: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Attach the task running the actor. Called once, straight after spawning it.
+    pub(crate) fn attach(&self, join: JoinHandle<()>) {
+        *self
+            .join
+            .try_lock()
+            .expect("actor task is attached before anything can join it") = Some(join);
+    }
+
+    pub(crate) fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    pub(crate) async fn stop_requested(&self) {
+        self.stop.notified().await
+    }
+
+    pub(crate) async fn join(&self) -> Result<()> {
+        let mut join = self.join.lock().await;
+        if let Some(handle) = join.as_mut() {
+            let result = handle.await;
+            *join = None;
+            result.map_err(|e| match e.is_panic() {
+                true => Error::Panicked(panic_message(e.into_panic())),
+                // Only happens while the runtime shuts down, the actor is gone either way
+                false => Error::MailboxClosed,
+            })?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        match self.join.try_lock() {
+            Ok(join) => join.as_ref().is_none_or(|handle| handle.is_finished()),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Addr
+/// The address of a running actor as returned by `run_actor`. Cheap to clone. It is how messages
+/// reach the actor and how its lifetime is controlled, so most actors need no handle type of
+/// their own.
+pub struct Addr<A> {
+    sender: MailboxSender<Box<dyn Envelope<A>>>,
+    lifecycle: Lifecycle,
+    metrics: Arc<Metrics>,
+}
+
+impl<A: Actor> Addr<A> {
+    pub(crate) fn new(
+        sender: MailboxSender<Box<dyn Envelope<A>>>,
+        lifecycle: Lifecycle,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            sender,
+            lifecycle,
+            metrics,
+        }
+    }
+
+    /// Send a message. What happens when the mailbox is full depends on its `Overflow` policy.
+    pub async fn send<M>(&self, msg: M) -> Result<()>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.sender.send(MessageEnvelope::boxed(msg, None)).await
+    }
+
+    /// Send without waiting for room in the mailbox.
+    pub fn try_send<M>(&self, msg: M) -> Result<()>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.sender.try_send(MessageEnvelope::boxed(msg, None))
+    }
+
+    /// Send a message and wait for the actor's response to it.
+    pub async fn ask<M>(&self, msg: M) -> Result<M::Response>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.ask_with(msg, None).await
+    }
+
+    /// As `ask` but gives up once `timeout` has elapsed without a response.
+    pub async fn ask_timeout<M>(&self, msg: M, timeout: Duration) -> Result<M::Response>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.ask_with(msg, Some(timeout)).await
+    }
+
+    async fn ask_with<M>(&self, msg: M, timeout: Option<Duration>) -> Result<M::Response>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(MessageEnvelope::boxed(msg, Some(send)))
+            .await?;
+        let reply = match timeout {
+            Some(duration) => tokio::time::timeout(duration, recv)
+                .await
+                .map_err(|_| Error::Timeout(duration))?,
+            None => recv.await,
+        };
+        reply.map_err(|_| Error::ReplyDropped)?
+    }
+
+    /// This address narrowed down to a single message type.
+    pub fn recipient<M>(&self) -> Recipient<M>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        Recipient(Arc::new(self.clone()))
+    }
+
+    /// Whether the actor is still accepting messages.
+    pub fn is_alive(&self) -> bool {
+        !self.sender.is_closed()
+    }
+
+    /// How many messages are waiting in the actor's mailbox.
+    pub fn mailbox_len(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// The messages waiting plus the one being handled, if any.
+    pub(crate) fn load(&self) -> usize {
+        self.mailbox_len() + self.metrics.is_handling() as usize
+    }
+
+    /// Ask the actor to stop. Messages already in its mailbox are still handled.
+    pub fn stop(&self) {
+        self.lifecycle.stop();
+    }
+
+    /// Wait for the actor's task to finish. Errors if the task panicked.
+    pub async fn join(&self) -> Result<()> {
+        self.lifecycle.join().await
+    }
+
+    /// Stop the actor and wait for it to finish.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.stop();
+        self.join().await
+    }
+
+    pub(crate) fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakAddr<A> {
+        WeakAddr {
+            sender: self.sender.downgrade(),
+            lifecycle: self.lifecycle.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// WeakAddr
+/// An address that does not keep the actor running, for tasks that feed an actor for as long as
+/// anyone else still uses it.
+pub(crate) struct WeakAddr<A> {
+    sender: WeakMailboxSender<Box<dyn Envelope<A>>>,
+    lifecycle: Lifecycle,
+    metrics: Arc<Metrics>,
+}
+
+impl<A: Actor> WeakAddr<A> {
+    /// `None` once every address has been dropped.
+    pub(crate) fn upgrade(&self) -> Option<Addr<A>> {
+        let sender = self.sender.upgrade()?;
+        Some(Addr::new(
+            sender,
+            self.lifecycle.clone(),
+            self.metrics.clone(),
+        ))
+    }
+
+    /// Resolves once the actor stops accepting messages.
+    pub(crate) async fn closed(&self) {
+        self.sender.closed().await
+    }
+}
+
+impl<A> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            lifecycle: self.lifecycle.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<A> fmt::Debug for Addr<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Addr")
+            .field("actor", &std::any::type_name::<A>())
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<A, M> ActorSender<M> for Addr<A>
+where
+    A: Handler<M>,
+    M: Message,
+{
+    async fn send(&self, msg: M) -> Result<()> {
+        Addr::send(self, msg).await
+    }
+
+    fn is_alive(&self) -> bool {
+        Addr::is_alive(self)
+    }
+}
+
+/// Recipient
+/// An `Addr` narrowed down to one message type. It hides the type of the actor behind it so
+/// handles and listeners need not be generic over the actor.
+pub struct Recipient<M: Message>(Arc<dyn MessageSender<M>>);
+
+impl<M: Message> Recipient<M> {
+    pub async fn send(&self, msg: M) -> Result<()> {
+        self.0.send(msg).await
+    }
+
+    /// Send without waiting for room in the mailbox.
+    pub fn try_send(&self, msg: M) -> Result<()> {
+        self.0.try_send(msg)
+    }
+
+    /// Send a message and wait for the actor's response to it.
+    pub async fn ask(&self, msg: M) -> Result<M::Response> {
+        self.0.ask(msg, None).await
+    }
+
+    /// As `ask` but gives up once `timeout` has elapsed without a response.
+    pub async fn ask_timeout(&self, msg: M, timeout: Duration) -> Result<M::Response> {
+        self.0.ask(msg, Some(timeout)).await
+    }
+
+    /// Whether the actor is still accepting messages.
+    pub fn is_alive(&self) -> bool {
+        self.0.is_alive()
+    }
+
+    /// Ask the actor to stop. Messages already in its mailbox are still handled.
+    pub fn stop(&self) {
+        self.0.stop()
+    }
+
+    /// Wait for the actor's task to finish. Errors if the task panicked.
+    pub async fn join(&self) -> Result<()> {
+        self.0.join().await
+    }
+
+    /// Stop the actor and wait for it to finish.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.stop();
+        self.join().await
+    }
+}
+
+impl<M: Message> Clone for Recipient<M> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<M: Message> fmt::Debug for Recipient<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recipient")
+            .field("message", &std::any::type_name::<M>())
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<M: Message> ActorSender<M> for Recipient<M> {
+    async fn send(&self, msg: M) -> Result<()> {
+        Recipient::send(self, msg).await
+    }
+
+    fn is_alive(&self) -> bool {
+        Recipient::is_alive(self)
+    }
+}
+
+/// The part of `Addr` that `Recipient` erases the actor type from.
+#[async_trait]
+trait MessageSender<M: Message>: Send + Sync {
+    async fn send(&self, msg: M) -> Result<()>;
+    fn try_send(&self, msg: M) -> Result<()>;
+    async fn ask(&self, msg: M, timeout: Option<Duration>) -> Result<M::Response>;
+    fn is_alive(&self) -> bool;
+    fn stop(&self);
+    async fn join(&self) -> Result<()>;
+}
+
+#[async_trait]
+impl<A, M> MessageSender<M> for Addr<A>
+where
+    A: Handler<M>,
+    M: Message,
+{
+    async fn send(&self, msg: M) -> Result<()> {
+        Addr::send(self, msg).await
+    }
+
+    fn try_send(&self, msg: M) -> Result<()> {
+        Addr::try_send(self, msg)
+    }
+
+    async fn ask(&self, msg: M, timeout: Option<Duration>) -> Result<M::Response> {
+        self.ask_with(msg, timeout).await
+    }
+
+    fn is_alive(&self) -> bool {
+        Addr::is_alive(self)
+    }
+
+    fn stop(&self) {
+        Addr::stop(self)
+    }
+
+    async fn join(&self) -> Result<()> {
+        Addr::join(self).await
+    }
 }
 
-pub fn run_actor<A, M>(actor: A, buffer: usize) -> mpsc::Sender<M>
+/// How a `Router` picks the worker for a message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Routing {
+    RoundRobin,
+    /// The worker with the fewest messages waiting in its mailbox or being handled
+    LeastLoaded,
+    /// The same worker for every message with the same `Message::routing_key`
+    KeyHash,
+}
+
+/// Router
+/// A pool of identical actors behind one address. Useful for stateless work that can be spread
+/// across cores. Messages to different workers are handled in no particular order.
+pub struct Router<A> {
+    workers: Arc<[Addr<A>]>,
+    routing: Routing,
+    next: Arc<AtomicUsize>,
+}
+
+impl<A: Actor> Router<A> {
+    /// Route over actors that are already running, supervised or not.
+    pub fn new(workers: impl IntoIterator<Item = Addr<A>>, routing: Routing) -> Self {
+        let workers: Arc<[Addr<A>]> = workers.into_iter().collect();
+        assert!(!workers.is_empty(), "a router needs at least one worker");
+        Self {
+            workers,
+            routing,
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Run `size` actors built by `factory`, each with a mailbox as described by `config`.
+    pub fn spawn(
+        size: usize,
+        routing: Routing,
+        config: impl Into<MailboxConfig>,
+        mut factory: impl FnMut() -> A,
+    ) -> Self {
+        let config = config.into();
+        Self::new((0..size).map(|_| run_actor(factory(), config)), routing)
+    }
+
+    fn route<M: Message>(&self, msg: &M) -> &Addr<A> {
+        let size = self.workers.len();
+        let index = match (self.routing, msg.routing_key()) {
+            (Routing::KeyHash, Some(key)) => (key % size as u64) as usize,
+            // Start looking from the next worker in turn so ties are spread out
+            (Routing::LeastLoaded, _) => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (start..start + size)
+                    .min_by_key(|i| self.workers[i % size].load())
+                    .unwrap_or(start)
+            }
+            _ => self.next.fetch_add(1, Ordering::Relaxed),
+        };
+        &self.workers[index % size]
+    }
+
+    pub async fn send<M>(&self, msg: M) -> Result<()>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.route(&msg).send(msg).await
+    }
+
+    /// Send without waiting for room in the chosen worker's mailbox.
+    pub fn try_send<M>(&self, msg: M) -> Result<()>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.route(&msg).try_send(msg)
+    }
+
+    /// Send a message and wait for the response of whichever worker handled it.
+    pub async fn ask<M>(&self, msg: M) -> Result<M::Response>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.route(&msg).ask(msg).await
+    }
+
+    /// As `ask` but gives up once `timeout` has elapsed without a response.
+    pub async fn ask_timeout<M>(&self, msg: M, timeout: Duration) -> Result<M::Response>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        self.route(&msg).ask_timeout(msg, timeout).await
+    }
+
+    /// This router narrowed down to a single message type.
+    pub fn recipient<M>(&self) -> Recipient<M>
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        Recipient(Arc::new(self.clone()))
+    }
+
+    /// Whether any worker is still accepting messages.
+    pub fn is_alive(&self) -> bool {
+        self.workers.iter().any(Addr::is_alive)
+    }
+
+    /// Ask every worker to stop.
+    pub fn stop(&self) {
+        self.workers.iter().for_each(Addr::stop);
+    }
+
+    /// Wait for every worker to finish. Errors if any of them panicked.
+    pub async fn join(&self) -> Result<()> {
+        for worker in self.workers.iter() {
+            worker.join().await?;
+        }
+        Ok(())
+    }
+
+    /// Stop every worker and wait for them to finish.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.stop();
+        self.join().await
+    }
+}
+
+impl<A> Clone for Router<A> {
+    fn clone(&self) -> Self {
+        Self {
+            workers: self.workers.clone(),
+            routing: self.routing,
+            next: self.next.clone(),
+        }
+    }
+}
+
+impl<A> fmt::Debug for Router<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("actor", &std::any::type_name::<A>())
+            .field("workers", &self.workers.len())
+            .field("routing", &self.routing)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<A, M> ActorSender<M> for Router<A>
+where
+    A: Handler<M>,
+    M: Message,
+{
+    async fn send(&self, msg: M) -> Result<()> {
+        Router::send(self, msg).await
+    }
+
+    fn is_alive(&self) -> bool {
+        Router::is_alive(self)
+    }
+}
+
+#[async_trait]
+impl<A, M> MessageSender<M> for Router<A>
 where
-    A: Actor<M>,
-    M: Send + 'static
+    A: Handler<M>,
+    M: Message,
 {
-    let (sender, receiver) = mpsc::channel(buffer);
+    async fn send(&self, msg: M) -> Result<()> {
+        Router::send(self, msg).await
+    }
+
+    fn try_send(&self, msg: M) -> Result<()> {
+        Router::try_send(self, msg)
+    }
+
+    async fn ask(&self, msg: M, timeout: Option<Duration>) -> Result<M::Response> {
+        self.route(&msg).ask_with(msg, timeout).await
+    }
+
+    fn is_alive(&self) -> bool {
+        Router::is_alive(self)
+    }
 
-    tokio::spawn(consume_actor(actor, receiver));
+    fn stop(&self) {
+        Router::stop(self)
+    }
 
-    sender
+    async fn join(&self) -> Result<()> {
+        Router::join(self).await
+    }
 }
 
-async fn consume_actor<A, M>(mut actor: A, mut receiver: Receiver<M>)
+/// Run CPU bound or otherwise blocking work on the runtime's blocking thread pool so it doesn't
+/// hold up other actors. A handler awaiting this still finishes before the actor's next message is
+/// taken, and a panic in `f` is raised in the handler as if `f` had run there. A simulation runs
+/// `f` inline to keep things deterministic.
+pub async fn blocking<F, T>(f: F) -> T
 where
-    A: Actor<M>,
-    M: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
 {
-    while let Some(msg) = receiver.recv().await {
-        if let Err(e) = actor.handle_message(msg).await {
-            eprintln!("Error handling message: {:?}", e);
+    #[cfg(test)]
+    if sim::is_active() {
+        return f();
+    }
+    match tokio::task::spawn_blocking(f).await {
+        Ok(output) => output,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("blocking task did not finish: {}", e),
+    }
+}
+
+/// Start `actor` on its own task. `config` is usually just a capacity, see `MailboxConfig` for
+/// unbounded mailboxes and other overflow policies.
+pub fn run_actor<A: Actor>(actor: A, config: impl Into<MailboxConfig>) -> Addr<A> {
+    spawn_actor(None, actor, config.into())
+}
+
+/// As `run_actor`, listing the actor in the registry under `name` rather than its type.
+pub fn run_named<A: Actor>(
+    name: impl Into<String>,
+    actor: A,
+    config: impl Into<MailboxConfig>,
+) -> Addr<A> {
+    spawn_actor(Some(name.into()), actor, config.into())
+}
+
+fn spawn_actor<A: Actor>(name: Option<String>, actor: A, config: MailboxConfig) -> Addr<A> {
+    let (sender, mailbox) = mailbox(config);
+    let lifecycle = Lifecycle::new();
+    let registration = Registration::new(name, sender.downgrade());
+    let metrics = registration.metrics();
+    let ctx = Context::new(sender.downgrade(), lifecycle.clone(), metrics.clone());
+
+    lifecycle.attach(tokio::spawn(
+        registration.hold(consume_actor(actor, mailbox, ctx)),
+    ));
+
+    Addr::new(sender, lifecycle, metrics)
+}
+
+async fn consume_actor<A: Actor>(mut actor: A, mut mailbox: Mailbox<A>, mut ctx: Context<A>) {
+    actor.started(&mut ctx).await;
+    while let Some(envelope) = next_message(&mut mailbox, &ctx).await {
+        // A failure has been reported by the envelope and the actor carries on regardless
+        let _ = envelope.handle(&mut actor, &mut ctx).await;
+    }
+    drain_actor(&mut actor, &mut mailbox, &mut ctx).await;
+}
+
+/// The next message for the actor or `None` once it should stop.
+pub(crate) async fn next_message<A: Actor>(
+    mailbox: &mut Mailbox<A>,
+    ctx: &Context<A>,
+) -> Option<Box<dyn Envelope<A>>> {
+    #[cfg(test)]
+    sim::yield_point().await;
+    tokio::select! {
+        biased;
+        _ = ctx.stop_requested() => None,
+        envelope = mailbox.recv() => envelope,
+    }
+}
+
+/// Close the mailbox, handle what is left in it, stop any children and run the stop hooks.
+pub(crate) async fn drain_actor<A: Actor>(
+    actor: &mut A,
+    mailbox: &mut Mailbox<A>,
+    ctx: &mut Context<A>,
+) {
+    mailbox.close();
+    actor.stopping(ctx).await;
+    ctx.cancel_timers();
+    while let Some(envelope) = mailbox.recv().await {
+        let _ = envelope.handle(actor, ctx).await;
+    }
+    ctx.stop_children().await;
+    actor.stopped(ctx).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::sim::Sim;
+
+    struct Answer;
+    impl Message for Answer {
+        type Response = u32;
+    }
+
+    struct Fail;
+    impl Message for Fail {
+        type Response = u32;
+    }
+
+    struct Stall;
+    impl Message for Stall {
+        type Response = ();
+    }
+
+    struct Explode;
+    impl Message for Explode {
+        type Response = u32;
+    }
+
+    struct Crunch(u32);
+    impl Message for Crunch {
+        type Response = u32;
+    }
+
+    struct Oracle;
+    impl Actor for Oracle {}
+
+    #[async_trait]
+    impl Handler<Answer> for Oracle {
+        async fn handle_message(&mut self, _: Answer, _: &mut Context<Self>) -> Result<u32> {
+            Ok(42)
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Fail> for Oracle {
+        async fn handle_message(&mut self, _: Fail, _: &mut Context<Self>) -> Result<u32> {
+            Err(Error::InvalidEvent("no answer".into()))
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Stall> for Oracle {
+        async fn handle_message(&mut self, _: Stall, _: &mut Context<Self>) -> Result<()> {
+            sleep(Duration::from_secs(60)).await;
+            Ok(())
         }
     }
+
+    #[async_trait]
+    impl Handler<Explode> for Oracle {
+        async fn handle_message(&mut self, _: Explode, _: &mut Context<Self>) -> Result<u32> {
+            panic!("boom")
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Crunch> for Oracle {
+        async fn handle_message(&mut self, msg: Crunch, _: &mut Context<Self>) -> Result<u32> {
+            Ok(blocking(move || match msg.0 {
+                0 => panic!("nothing to crunch"),
+                n => n * 2,
+            })
+            .await)
+        }
+    }
+
+    /// Naps for the given duration, with an optional deadline of its own
+    struct Nap(Duration, Option<Duration>);
+    impl Message for Nap {
+        type Response = ();
+
+        fn deadline(&self) -> Option<Duration> {
+            self.1
+        }
+    }
+
+    struct Napper;
+    impl Actor for Napper {
+        fn deadline(&self) -> Option<Duration> {
+            Some(Duration::from_millis(100))
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Nap> for Napper {
+        async fn handle_message(&mut self, msg: Nap, _: &mut Context<Self>) -> Result<()> {
+            sleep(msg.0).await;
+            Ok(())
+        }
+    }
+
+    struct Worker(usize);
+    impl Actor for Worker {}
+
+    struct WhoAreYou(Option<u64>);
+    impl Message for WhoAreYou {
+        type Response = usize;
+
+        fn routing_key(&self) -> Option<u64> {
+            self.0
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Nap> for Worker {
+        async fn handle_message(&mut self, msg: Nap, _: &mut Context<Self>) -> Result<()> {
+            sleep(msg.0).await;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Handler<WhoAreYou> for Worker {
+        async fn handle_message(&mut self, _: WhoAreYou, _: &mut Context<Self>) -> Result<usize> {
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ask() -> Result<()> {
+        let oracle = run_actor(Oracle, 8);
+
+        assert_eq!(oracle.ask(Answer).await?, 42);
+
+        // The asker gets the handler's own error
+        let failed = oracle.ask(Fail).await;
+        assert_eq!(failed, Err(Error::InvalidEvent("no answer".into())));
+
+        let timeout = Duration::from_millis(10);
+        let stalled = oracle.ask_timeout(Stall, timeout).await;
+        assert_eq!(stalled, Err(Error::Timeout(timeout)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deadline() -> Result<()> {
+        let _sim = Sim::new(0);
+        let napper = run_actor(Napper, 8);
+        let nap = |millis, deadline| Nap(Duration::from_millis(millis), deadline);
+
+        assert_eq!(napper.ask(nap(50, None)).await, Ok(()));
+        let overslept = napper.ask(nap(1000, None)).await;
+        assert_eq!(
+            overslept,
+            Err(Error::DeadlineExceeded(Duration::from_millis(100)))
+        );
+
+        // A message's own deadline wins over the actor's
+        let short = Some(Duration::from_millis(10));
+        let overslept = napper.ask(nap(50, short)).await;
+        assert_eq!(
+            overslept,
+            Err(Error::DeadlineExceeded(Duration::from_millis(10)))
+        );
+        assert_eq!(napper.ask(nap(0, None)).await, Ok(()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_panic_is_isolated_to_the_message() -> Result<()> {
+        let oracle = run_actor(Oracle, 8);
+
+        let panicked = oracle.ask(Explode).await;
+        assert_eq!(panicked, Err(Error::Panicked("boom".into())));
+        assert_eq!(oracle.ask(Answer).await?, 42);
+
+        // Including panics on the blocking pool
+        let panicked = oracle.ask(Crunch(0)).await;
+        assert_eq!(panicked, Err(Error::Panicked("nothing to crunch".into())));
+        assert_eq!(oracle.ask(Crunch(21)).await?, 42);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_router() -> Result<()> {
+        let mut ids = 0..;
+        let workers = Router::spawn(3, Routing::RoundRobin, 8, || Worker(ids.next().unwrap()));
+        let mut answered = vec![];
+        for _ in 0..4 {
+            answered.push(workers.ask(WhoAreYou(None)).await?);
+        }
+        assert_eq!(answered, vec![0, 1, 2, 0]);
+
+        let mut ids = 0..;
+        let workers = Router::spawn(3, Routing::KeyHash, 8, || Worker(ids.next().unwrap()));
+        for _ in 0..3 {
+            assert_eq!(workers.ask(WhoAreYou(Some(7))).await?, 1);
+        }
+
+        workers.shutdown().await?;
+        assert!(!workers.is_alive());
+
+        // A worker busy with a message counts as loaded even with nothing queued
+        let mut ids = 0..;
+        let workers = Router::spawn(2, Routing::LeastLoaded, 8, || Worker(ids.next().unwrap()));
+        workers.send(Nap(Duration::from_millis(50), None)).await?;
+        while workers.workers[0].mailbox_len() > 0 {
+            tokio::task::yield_now().await;
+        }
+        for _ in 0..2 {
+            assert_eq!(workers.ask(WhoAreYou(None)).await?, 1);
+        }
+
+        Ok(())
+    }
+
+    struct Record(u32);
+    impl Message for Record {
+        type Response = ();
+    }
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn record(&self, entry: impl Into<String>) {
+            self.0.lock().unwrap().push(entry.into());
+        }
+    }
+
+    #[async_trait]
+    impl Actor for Recorder {
+        async fn started(&mut self, _: &mut Context<Self>) {
+            self.record("started");
+        }
+
+        async fn stopping(&mut self, ctx: &mut Context<Self>) {
+            self.record("stopping");
+            // Too late for new messages, only the queued ones are still handled
+            if let Some(Err(e)) = ctx.address().map(|addr| addr.try_send(Record(3))) {
+                self.record(e.to_string());
+            }
+        }
+
+        async fn stopped(&mut self, _: &mut Context<Self>) {
+            self.record("stopped");
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Record> for Recorder {
+        async fn handle_message(&mut self, msg: Record, _: &mut Context<Self>) -> Result<()> {
+            self.record(msg.0.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle() -> Result<()> {
+        let log = Arc::new(Mutex::new(vec![]));
+        let recorder = run_actor(Recorder(log.clone()), 8);
+
+        recorder.send(Record(1)).await?;
+        recorder.send(Record(2)).await?;
+        recorder.shutdown().await?;
+
+        assert!(recorder.send(Record(3)).await.is_err());
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "started",
+                "stopping",
+                "actor mailbox is closed",
+                "1",
+                "2",
+                "stopped"
+            ]
+        );
+
+        Ok(())
+    }
 }
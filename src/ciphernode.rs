@@ -1,41 +1,66 @@
+use std::time::Duration;
+
 use crate::{
-    actor_traits::{Actor, ActorSender},
+    actor_traits::{blocking, Actor, ActorSender, Context, Handler, Recipient},
     encryptor::{Encryptor, Plaintext},
-    event::EnclaveEvent,
+    error::Result,
+    event::{EnclaveEvent, EventEnvelope},
     event_dispatcher::EventDispatcher,
     fhe::{Fhe, Rng},
-    run_actor,
     store::Store,
+    supervisor::Supervisor,
 };
 use async_trait::*;
-use tokio::sync::mpsc;
-
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Clone)]
 pub struct Ciphernode {
-    sender: mpsc::Sender<EnclaveEvent>,
+    addr: Recipient<EnclaveEvent>,
 }
 
 impl Ciphernode {
-    pub fn new<D, S, R, E>(dispatcher: D, store: S, fhe: Fhe<R>, encryptor: E) -> Self
+    pub fn new<D, S, R, E>(
+        name: impl Into<String>,
+        dispatcher: D,
+        store: S,
+        fhe: Fhe<R>,
+        encryptor: E,
+    ) -> Self
     where
-        S: Store,
-        D: EventDispatcher<EnclaveEvent>,
+        S: Store + Clone,
+        D: EventDispatcher<EnclaveEvent> + Clone,
         R: Rng,
-        E: Encryptor,
+        E: Encryptor + Clone,
+        Fhe<R>: Clone,
     {
-        let actor = CiphernodeActor::new(dispatcher, store, fhe, encryptor);
-        let sender = run_actor(actor, 8);
-        Ciphernode { sender }
+        // A bad message restarts the node with fresh state rather than taking it down
+        let addr = Supervisor::new(move || {
+            CiphernodeActor::new(
+                dispatcher.clone(),
+                store.clone(),
+                fhe.clone(),
+                encryptor.clone(),
+            )
+        })
+        .name(name)
+        .run(8)
+        .recipient();
+        Ciphernode { addr }
+    }
+
+    /// Stop the node once its queued events are handled and wait for it to finish.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.addr.shutdown().await
     }
 }
 
 #[async_trait]
-impl ActorSender<EnclaveEvent> for Ciphernode {
-    async fn send(&self, msg: EnclaveEvent) -> Result<()> {
-        Ok(self.sender.send(msg).await?)
+impl ActorSender<EventEnvelope> for Ciphernode {
+    async fn send(&self, msg: EventEnvelope) -> Result<()> {
+        self.addr.send(msg.event).await
+    }
+
+    fn is_alive(&self) -> bool {
+        self.addr.is_alive()
     }
 }
 
@@ -52,6 +77,7 @@ where
     D: EventDispatcher<EnclaveEvent>,
     R: Rng,
     E: Encryptor,
+    Fhe<R>: Clone,
 {
     pub fn new(dispatcher: D, store: S, fhe: Fhe<R>, encryptor: E) -> Self {
         Self {
@@ -63,12 +89,16 @@ where
     }
 
     async fn on_computation_requested(&mut self, e3_id: &str) -> Result<()> {
-        let (sk, pk) = self.fhe.generate_keyshare()?;
+        // Key generation is CPU bound so keep it off the runtime's worker threads
+        let fhe = self.fhe.clone();
+        let (sk, pk) = blocking(move || fhe.generate_keyshare()).await?;
         let e_sk = self.encryptor.encrypt(Plaintext::new(sk.into())).await?;
-        
-        self.store.insert(format!("{}/sk",e3_id), e_sk);
-        self.store.insert(format!("{}/pk",e3_id), pk.clone());
-        
+
+        self.store.insert(format!("{}/sk", e3_id), e_sk).await?;
+        self.store
+            .insert(format!("{}/pk", e3_id), pk.clone())
+            .await?;
+
         let _ = self
             .dispatcher
             .send(EnclaveEvent::KeyshareCreated {
@@ -80,15 +110,30 @@ where
     }
 }
 
+impl<S, D, R, E> Actor for CiphernodeActor<S, D, R, E>
+where
+    S: Store,
+    D: EventDispatcher<EnclaveEvent>,
+    R: Rng,
+    E: Encryptor,
+    Fhe<R>: Clone,
+{
+    // A node stuck on key generation or encryption is restarted rather than left to stall the E3
+    fn deadline(&self) -> Option<Duration> {
+        Some(Duration::from_secs(60))
+    }
+}
+
 #[async_trait]
-impl<S, D, R, E> Actor<EnclaveEvent> for CiphernodeActor<S, D, R, E>
+impl<S, D, R, E> Handler<EnclaveEvent> for CiphernodeActor<S, D, R, E>
 where
     S: Store,
     D: EventDispatcher<EnclaveEvent>,
     R: Rng,
     E: Encryptor,
+    Fhe<R>: Clone,
 {
-    async fn handle_message(&mut self, msg: EnclaveEvent) -> Result<()> {
+    async fn handle_message(&mut self, msg: EnclaveEvent, _: &mut Context<Self>) -> Result<()> {
         match msg {
             EnclaveEvent::ComputationRequested { e3_id, .. } => {
                 self.on_computation_requested(&e3_id).await?
@@ -0,0 +1,253 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use tokio::{
+    task::AbortHandle,
+    time::{interval, sleep, MissedTickBehavior},
+};
+
+use crate::{
+    actor_traits::{
+        run_actor, Actor, Addr, Envelope, Handler, Lifecycle, MailboxConfig, Message,
+        MessageEnvelope,
+    },
+    mailbox::WeakMailboxSender,
+    registry::Metrics,
+    trace,
+};
+
+/// Context
+/// Handed to an actor alongside every message. Gives the actor its own address and lets it
+/// schedule messages to itself and spawn children. Timers and children belong to the actor and
+/// are torn down when it stops.
+pub struct Context<A> {
+    sender: WeakMailboxSender<Box<dyn Envelope<A>>>,
+    lifecycle: Lifecycle,
+    metrics: Arc<Metrics>,
+    timers: Vec<AbortHandle>,
+    children: Vec<Lifecycle>,
+}
+
+impl<A: Actor> Context<A> {
+    pub(crate) fn new(
+        sender: WeakMailboxSender<Box<dyn Envelope<A>>>,
+        lifecycle: Lifecycle,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            sender,
+            lifecycle,
+            metrics,
+            timers: vec![],
+            children: vec![],
+        }
+    }
+
+    /// The actor's own address. `None` once every other address has been dropped and the actor is
+    /// on its way out, as the context does not keep the actor alive by itself.
+    pub fn address(&self) -> Option<Addr<A>> {
+        let sender = self.sender.upgrade()?;
+        Some(Addr::new(
+            sender,
+            self.lifecycle.clone(),
+            self.metrics.clone(),
+        ))
+    }
+
+    /// Stop the actor once the current message has been handled.
+    pub fn stop(&self) {
+        self.lifecycle.stop();
+    }
+
+    /// Send `msg` to this actor after `delay`. It is traced as caused by the message being handled.
+    pub fn notify_later<M>(&mut self, msg: M, delay: Duration) -> TimerHandle
+    where
+        A: Handler<M>,
+        M: Message,
+    {
+        let sender = self.sender.clone();
+        self.add_timer(tokio::spawn(trace::inherit(async move {
+            sleep(delay).await;
+            if let Some(sender) = sender.upgrade() {
+                let _ = sender.send(MessageEnvelope::boxed(msg, None)).await;
+            }
+        })))
+    }
+
+    /// Send the message built by `build` to this actor every `period`, starting one period from
+    /// now. Ticks missed while the mailbox was full are skipped rather than bunched up.
+    pub fn run_interval<M, F>(&mut self, period: Duration, mut build: F) -> TimerHandle
+    where
+        A: Handler<M>,
+        M: Message,
+        F: FnMut() -> M + Send + 'static,
+    {
+        let sender = self.sender.clone();
+        self.add_timer(tokio::spawn(async move {
+            let mut ticks = interval(period);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let Some(sender) = sender.upgrade() else {
+                    break;
+                };
+                if sender
+                    .send(MessageEnvelope::boxed(build(), None))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }))
+    }
+
+    /// Run `actor` as a child of this one. The child is stopped after this actor has drained its
+    /// mailbox and before its `stopped` hook runs.
+    pub fn spawn_child<B: Actor>(&mut self, actor: B, config: impl Into<MailboxConfig>) -> Addr<B> {
+        let addr = run_actor(actor, config);
+        self.children.retain(|child| !child.is_finished());
+        self.children.push(addr.lifecycle().clone());
+        addr
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub(crate) async fn stop_requested(&self) {
+        self.lifecycle.stop_requested().await
+    }
+
+    pub(crate) fn cancel_timers(&mut self) {
+        for timer in self.timers.drain(..) {
+            timer.abort();
+        }
+    }
+
+    pub(crate) async fn stop_children(&mut self) {
+        for child in self.children.iter() {
+            child.stop();
+        }
+        for child in self.children.drain(..) {
+            if let Err(e) = child.join().await {
+                tracing::error!(error = %e, "child actor failed while stopping");
+            }
+        }
+    }
+
+    fn add_timer(&mut self, timer: tokio::task::JoinHandle<()>) -> TimerHandle {
+        self.timers.retain(|timer| !timer.is_finished());
+        let abort = timer.abort_handle();
+        self.timers.push(abort.clone());
+        TimerHandle(abort)
+    }
+}
+
+impl<A> Drop for Context<A> {
+    fn drop(&mut self) {
+        for timer in self.timers.iter() {
+            timer.abort();
+        }
+        for child in self.children.iter() {
+            child.stop();
+        }
+    }
+}
+
+impl<A> fmt::Debug for Context<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("actor", &std::any::type_name::<A>())
+            .field("timers", &self.timers.len())
+            .field("children", &self.children.len())
+            .finish()
+    }
+}
+
+/// TimerHandle
+/// Returned when scheduling a message so the schedule can be called off.
+#[derive(Debug, Clone)]
+pub struct TimerHandle(AbortHandle);
+
+impl TimerHandle {
+    pub fn cancel(&self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::error::Result;
+
+    struct Tick;
+    impl Message for Tick {
+        type Response = ();
+    }
+
+    struct Idle;
+    impl Actor for Idle {}
+
+    struct Parent {
+        ticks: mpsc::UnboundedSender<()>,
+        child: Option<Addr<Idle>>,
+    }
+
+    #[async_trait]
+    impl Actor for Parent {
+        async fn started(&mut self, ctx: &mut Context<Self>) {
+            self.child = Some(ctx.spawn_child(Idle, 1));
+            ctx.notify_later(Tick, Duration::from_millis(5));
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Tick> for Parent {
+        async fn handle_message(&mut self, _: Tick, ctx: &mut Context<Self>) -> Result<()> {
+            let _ = self.ticks.send(());
+            ctx.notify_later(Tick, Duration::from_millis(5));
+            Ok(())
+        }
+    }
+
+    struct GetChild;
+    impl Message for GetChild {
+        type Response = Option<Addr<Idle>>;
+    }
+
+    #[async_trait]
+    impl Handler<GetChild> for Parent {
+        async fn handle_message(
+            &mut self,
+            _: GetChild,
+            _: &mut Context<Self>,
+        ) -> Result<Option<Addr<Idle>>> {
+            Ok(self.child.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timers_and_children_stop_with_parent() -> Result<()> {
+        let (ticks, mut received) = mpsc::unbounded_channel();
+        let parent = run_actor(Parent { ticks, child: None }, 8);
+
+        received.recv().await;
+        received.recv().await;
+        let child = parent.ask(GetChild).await?.unwrap();
+        assert!(child.is_alive());
+
+        parent.shutdown().await?;
+        child.join().await?;
+        assert!(!child.is_alive());
+        // The parent is gone and its pending tick with it
+        assert!(received.recv().await.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::{fmt, time::SystemTime};
+
+use async_trait::async_trait;
+
+use crate::{
+    actor_traits::*,
+    error::{Error, Result},
+    event::EventEnvelope,
+    event_dispatcher::{Listener, SubscriptionId},
+};
+
+/// DeadLetter
+/// An event that could not be delivered to one of its listeners.
+#[derive(Clone)]
+pub struct DeadLetter {
+    pub envelope: EventEnvelope,
+    pub subscription: SubscriptionId,
+    pub error: Error,
+    pub at: SystemTime,
+    listener: Listener<EventEnvelope>,
+}
+
+impl DeadLetter {
+    pub(crate) fn new(
+        envelope: EventEnvelope,
+        subscription: SubscriptionId,
+        listener: Listener<EventEnvelope>,
+        error: Error,
+    ) -> Self {
+        Self {
+            envelope,
+            subscription,
+            error,
+            at: SystemTime::now(),
+            listener,
+        }
+    }
+}
+
+impl fmt::Debug for DeadLetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetter")
+            .field("envelope", &self.envelope)
+            .field("subscription", &self.subscription)
+            .field("error", &self.error)
+            .field("at", &self.at)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Record(DeadLetter);
+
+impl Message for Record {
+    type Response = ();
+}
+
+/// Ask for the dead letters, optionally taking them out of the queue
+struct GetDeadLetters {
+    take: bool,
+}
+
+impl Message for GetDeadLetters {
+    type Response = Vec<DeadLetter>;
+}
+
+/// DeadLetters
+/// Keeps every event that could not be delivered until it is redriven. Nothing is ever dropped
+/// so the queue is unbounded.
+#[derive(Debug, Clone)]
+pub struct DeadLetters {
+    addr: Addr<DeadLettersActor>,
+}
+
+impl DeadLetters {
+    pub fn new() -> Self {
+        let addr = run_actor(
+            DeadLettersActor { letters: vec![] },
+            MailboxConfig::unbounded(),
+        );
+        DeadLetters { addr }
+    }
+
+    /// Never waits as the queue is unbounded.
+    pub fn record(&self, letter: DeadLetter) -> Result<()> {
+        self.addr.try_send(Record(letter))
+    }
+
+    /// The dead letters so far, oldest first.
+    pub async fn list(&self) -> Result<Vec<DeadLetter>> {
+        self.addr.ask(GetDeadLetters { take: false }).await
+    }
+
+    /// Send every dead letter straight to its listener again, outside the order of the events it
+    /// is being delivered. Letters that fail again go back in the queue with the new error.
+    /// Returns how many were delivered.
+    pub async fn redrive(&self) -> Result<usize> {
+        let mut delivered = 0;
+        for letter in self.addr.ask(GetDeadLetters { take: true }).await? {
+            match letter.listener.send(letter.envelope.clone()).await {
+                Ok(()) => delivered += 1,
+                Err(error) => self.record(DeadLetter::new(
+                    letter.envelope,
+                    letter.subscription,
+                    letter.listener,
+                    error,
+                ))?,
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+struct DeadLettersActor {
+    letters: Vec<DeadLetter>,
+}
+
+impl Actor for DeadLettersActor {}
+
+#[async_trait]
+impl Handler<Record> for DeadLettersActor {
+    async fn handle_message(&mut self, msg: Record, _: &mut Context<Self>) -> Result<()> {
+        tracing::warn!(
+            subscription = ?msg.0.subscription,
+            seq = msg.0.envelope.seq,
+            kind = ?msg.0.envelope.event.kind(),
+            error = %msg.0.error,
+            "event could not be delivered"
+        );
+        self.letters.push(msg.0);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Handler<GetDeadLetters> for DeadLettersActor {
+    async fn handle_message(
+        &mut self,
+        msg: GetDeadLetters,
+        _: &mut Context<Self>,
+    ) -> Result<Vec<DeadLetter>> {
+        Ok(match msg.take {
+            true => std::mem::take(&mut self.letters),
+            false => self.letters.clone(),
+        })
+    }
+}
@@ -2,17 +2,19 @@ use std::fmt::Debug;
 
 use aes_gcm::{
     aead::{Aead, KeyInit, OsRng},
-    AeadCore, Aes256Gcm, Key,
+    AeadCore, Aes256Gcm,
 };
 use async_trait::async_trait;
-use tokio::sync::{mpsc, oneshot};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-use crate::actor_traits::*;
-
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+use crate::{
+    actor_traits::*,
+    error::{Error, Result},
+};
 
-// TODO: Zeroize etc
+/// Plaintext
+/// Data to encrypt, wiped from memory once it has been dropped.
+#[derive(Zeroize, ZeroizeOnDrop)]
 pub struct Plaintext(Vec<u8>);
 
 impl Plaintext {
@@ -28,16 +30,18 @@ impl From<Vec<u8>> for Plaintext {
 }
 
 impl From<Plaintext> for Vec<u8> {
-    fn from(plaintext: Plaintext) -> Self {
-        plaintext.0
+    fn from(mut plaintext: Plaintext) -> Self {
+        // The caller takes over wiping the data
+        std::mem::take(&mut plaintext.0)
     }
 }
 
-pub enum EncryptorMessage {
-    Encrypt {
-        plaintext: Plaintext,
-        reply: oneshot::Sender<Vec<u8>>,
-    },
+pub struct Encrypt {
+    pub plaintext: Plaintext,
+}
+
+impl Message for Encrypt {
+    type Response = Vec<u8>;
 }
 
 #[async_trait]
@@ -47,62 +51,69 @@ pub trait Encryptor: Send + 'static {
 
 #[derive(Debug, Clone)]
 pub struct AesEncryptor {
-    sender: mpsc::Sender<EncryptorMessage>,
+    addr: Router<EncryptorActor>,
 }
 
 impl AesEncryptor {
     pub fn new(key: Vec<u8>) -> Self {
-        let actor = EncryptorActor::new(key);
-        let sender = run_actor(actor, 8);
-        AesEncryptor { sender }
+        // Encryption is stateless so spread it over a worker per core
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let addr = Router::spawn(workers, Routing::LeastLoaded, 8, || {
+            EncryptorActor::new(key.clone())
+        });
+        AesEncryptor { addr }
     }
 }
 
 #[async_trait]
 impl Encryptor for AesEncryptor {
     async fn encrypt(&self, plaintext: Plaintext) -> Result<Vec<u8>> {
-        let (send, recv) = oneshot::channel();
-        let _ = self
-            .sender
-            .send(EncryptorMessage::Encrypt {
-                plaintext,
-                reply: send,
-            })
-            .await;
-        Ok(recv.await?)
+        self.addr.ask(Encrypt { plaintext }).await
     }
 }
 
 struct EncryptorActor {
-    key: Vec<u8>,
+    key: Zeroizing<Vec<u8>>,
 }
 
 impl EncryptorActor {
     pub fn new(key: Vec<u8>) -> Self {
-        Self { key }
+        Self {
+            key: Zeroizing::new(key),
+        }
     }
 
     fn encrypt(&self, data: Plaintext) -> Result<Vec<u8>> {
-        let serialized: Vec<u8> = data.into();
-        let k = Key::<Aes256Gcm>::from_slice(&self.key);
-        let cipher = Aes256Gcm::new(k);
+        let cipher = Aes256Gcm::new_from_slice(&self.key)
+            .map_err(|_| Error::Crypto(format!("key must be 32 bytes, got {}", self.key.len())))?;
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-        let ciphertext = cipher
-            .encrypt(&nonce, serialized.as_ref())
-            .expect("Encryption failed"); // TODO: fix this when tidying up errors
-        Ok(ciphertext)
+        cipher
+            .encrypt(&nonce, data.0.as_ref())
+            .map_err(|e| Error::Crypto(e.to_string()))
     }
 }
 
+impl Actor for EncryptorActor {}
+
 #[async_trait]
-impl Actor<EncryptorMessage> for EncryptorActor {
-    async fn handle_message(&mut self, msg: EncryptorMessage) -> Result<()> {
-        match msg {
-            EncryptorMessage::Encrypt { reply, plaintext } => {
-                let encrypted = self.encrypt(plaintext)?;
-                let _ = reply.send(encrypted);
-            }
-        }
-        Ok(())
+impl Handler<Encrypt> for EncryptorActor {
+    async fn handle_message(&mut self, msg: Encrypt, _: &mut Context<Self>) -> Result<Vec<u8>> {
+        self.encrypt(msg.plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wrong_key_length() {
+        let encryptor = AesEncryptor::new(b"short".to_vec());
+        let encrypted = encryptor.encrypt(Plaintext::new(b"secret".to_vec())).await;
+
+        // A bad key is the encryptor failing at its work, not an actor that can't be reached
+        let error = encrypted.unwrap_err();
+        assert_eq!(error, Error::Crypto("key must be 32 bytes, got 5".into()));
+        assert!(!error.is_delivery_failure());
     }
 }
@@ -0,0 +1,101 @@
+use std::{fmt, time::Duration};
+
+/// Error
+/// Everything that can go wrong in this crate. The first few variants mean a message never got a
+/// proper answer from the actor it was meant for, the rest are failures of the work itself.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The actor's mailbox is closed so the message was never delivered
+    MailboxClosed,
+    /// The actor's mailbox has no room for the message
+    MailboxFull,
+    /// The actor did not answer, most likely because its handler failed
+    ReplyDropped,
+    /// No reply arrived within the given duration
+    Timeout(Duration),
+    /// The handler panicked while handling the message
+    Panicked(String),
+    /// The handler was cut short for running past its deadline
+    DeadlineExceeded(Duration),
+    /// The connection to an actor in another process failed
+    Remote(String),
+    /// A message could not be encoded for or decoded from a connection
+    Wire(String),
+    /// Encryption or key generation failed
+    Crypto(String),
+    /// Data could not be stored or loaded
+    Storage(String),
+    /// An event could not be handled as it makes no sense in the current state
+    InvalidEvent(String),
+    /// Past events were asked for from a dispatcher that keeps no history
+    NoHistory,
+}
+
+impl Error {
+    /// Whether the message didn't get through to a working actor, as opposed to the actor
+    /// failing at what it was asked to do. A handler that panicked or ran past its deadline got
+    /// the message, so those count as failures of the work.
+    pub fn is_delivery_failure(&self) -> bool {
+        matches!(
+            self,
+            Error::MailboxClosed
+                | Error::MailboxFull
+                | Error::ReplyDropped
+                | Error::Timeout(_)
+                | Error::Remote(_)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MailboxClosed => write!(f, "actor mailbox is closed"),
+            Error::MailboxFull => write!(f, "actor mailbox is full"),
+            Error::ReplyDropped => write!(f, "actor dropped the reply"),
+            Error::Timeout(d) => write!(f, "no reply within {:?}", d),
+            Error::Panicked(p) => write!(f, "actor panicked: {}", p),
+            Error::DeadlineExceeded(d) => write!(f, "handler ran past its deadline of {:?}", d),
+            Error::Remote(e) => write!(f, "remote actor unreachable: {}", e),
+            Error::Wire(e) => write!(f, "malformed message: {}", e),
+            Error::Crypto(e) => write!(f, "crypto failure: {}", e),
+            Error::Storage(e) => write!(f, "storage failure: {}", e),
+            Error::InvalidEvent(e) => write!(f, "invalid event: {}", e),
+            Error::NoHistory => write!(f, "no history is kept to replay"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivery_failures() {
+        let second = Duration::from_secs(1);
+        for undelivered in [
+            Error::MailboxClosed,
+            Error::MailboxFull,
+            Error::ReplyDropped,
+            Error::Timeout(second),
+            Error::Remote("refused".into()),
+        ] {
+            assert!(undelivered.is_delivery_failure(), "{:?}", undelivered);
+        }
+        for failed in [
+            Error::Panicked("boom".into()),
+            Error::DeadlineExceeded(second),
+            Error::Wire("truncated".into()),
+            Error::Crypto("bad key".into()),
+            Error::Storage("disk full".into()),
+            Error::InvalidEvent("unknown e3".into()),
+            Error::NoHistory,
+        ] {
+            assert!(!failed.is_delivery_failure(), "{:?}", failed);
+        }
+    }
+}
@@ -1,11 +1,22 @@
-use crate::{event_dispatcher::Listener, fhe::PublicKeyShare};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    actor_traits::Message,
+    error::{Error, Result},
+    fhe::{FheParams, PublicKeyShare},
+    remote::{to_wire, Wire, WireReader, WireWriter},
+};
 
 // type Error = Box<dyn std::error::Error>;
 // type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Clone, Debug)]
 pub enum EnclaveEvent {
-    RegisterListener(Listener),
     ComputationRequested {
         e3_id: String,
         // computation_type: ??, // TODO:
@@ -21,3 +32,215 @@ pub enum EnclaveEvent {
         keyshare: PublicKeyShare,
     },
 }
+
+/// EventKind
+/// Which variant of `EnclaveEvent` an event is, for subscribing to some kinds of event only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    ComputationRequested,
+    KeyshareCreated,
+}
+
+impl EnclaveEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            EnclaveEvent::ComputationRequested { .. } => EventKind::ComputationRequested,
+            EnclaveEvent::KeyshareCreated { .. } => EventKind::KeyshareCreated,
+        }
+    }
+
+    /// The E3 this event is part of
+    pub fn e3_id(&self) -> &str {
+        match self {
+            EnclaveEvent::ComputationRequested { e3_id, .. }
+            | EnclaveEvent::KeyshareCreated { e3_id, .. } => e3_id,
+        }
+    }
+
+    /// Derived from the event's content, so the same event published twice has the same id in
+    /// every process and every run. It is the start of the SHA-256 of the event's wire encoding.
+    pub fn id(&self) -> EventId {
+        // Only fails for fields over 4GiB, which no event has
+        let encoded = to_wire(self).expect("events can always be encoded");
+        let digest = Sha256::digest(encoded);
+        EventId(u64::from_be_bytes(digest[..8].try_into().unwrap()))
+    }
+}
+
+impl Message for EnclaveEvent {
+    type Response = ();
+}
+
+/// EventId
+/// Identifies an event by its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventId(u64);
+
+/// EventEnvelope
+/// An event as the `EventBus` delivers it to listeners.
+#[derive(Clone, Debug)]
+pub struct EventEnvelope {
+    /// Counts up from 1 across every event the bus has dispatched, with no gaps
+    pub seq: u64,
+    pub id: EventId,
+    /// Who published the event
+    pub source: Arc<str>,
+    /// When the bus dispatched the event
+    pub at: SystemTime,
+    pub event: EnclaveEvent,
+}
+
+impl Message for EventEnvelope {
+    type Response = ();
+}
+
+impl Wire for EventEnvelope {
+    fn encode(&self, out: &mut WireWriter) -> Result<()> {
+        self.seq.encode(out)?;
+        self.id.0.encode(out)?;
+        self.source.to_string().encode(out)?;
+        let at = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| Error::Wire(e.to_string()))?;
+        at.as_secs().encode(out)?;
+        at.subsec_nanos().encode(out)?;
+        self.event.encode(out)
+    }
+
+    fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+        Ok(EventEnvelope {
+            seq: u64::decode(input)?,
+            id: EventId(u64::decode(input)?),
+            source: String::decode(input)?.into(),
+            at: UNIX_EPOCH + Duration::new(u64::decode(input)?, u32::decode(input)?),
+            event: EnclaveEvent::decode(input)?,
+        })
+    }
+}
+
+// A keyshare travels as bytes and is read back with the receiver's own `FheParams`, which it
+// passes to `Server::bind_with`.
+impl Wire for EnclaveEvent {
+    fn encode(&self, out: &mut WireWriter) -> Result<()> {
+        match self {
+            EnclaveEvent::ComputationRequested {
+                e3_id,
+                ciphernode_group_length,
+                ciphernode_threshold,
+                sortition_seed,
+            } => {
+                0u8.encode(out)?;
+                e3_id.encode(out)?;
+                ciphernode_group_length.encode(out)?;
+                ciphernode_threshold.encode(out)?;
+                sortition_seed.encode(out)
+            }
+            EnclaveEvent::KeyshareCreated { e3_id, keyshare } => {
+                1u8.encode(out)?;
+                e3_id.encode(out)?;
+                keyshare.as_bytes().encode(out)
+            }
+        }
+    }
+
+    fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+        match u8::decode(input)? {
+            0 => Ok(EnclaveEvent::ComputationRequested {
+                e3_id: String::decode(input)?,
+                ciphernode_group_length: u32::decode(input)?,
+                ciphernode_threshold: u32::decode(input)?,
+                sortition_seed: u32::decode(input)?,
+            }),
+            1 => {
+                let e3_id = String::decode(input)?;
+                let params = input.context::<FheParams>()?;
+                Ok(EnclaveEvent::KeyshareCreated {
+                    e3_id,
+                    keyshare: params.keyshare_from_bytes(&Vec::decode(input)?)?,
+                })
+            }
+            tag => Err(Error::Wire(format!("unknown event tag {}", tag))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::ready,
+        sync::{Arc, Mutex},
+    };
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use crate::{
+        actor_traits::ActorSender,
+        fhe::Fhe,
+        remote::{from_wire, to_wire, Endpoint, RemoteAddr, Server},
+        testing::{eventually, Inbox, Received},
+    };
+
+    fn first(received: &Received<EnclaveEvent>) -> Option<EnclaveEvent> {
+        received.lock().unwrap().first().cloned()
+    }
+
+    #[test]
+    fn test_event_id() {
+        let request = EnclaveEvent::ComputationRequested {
+            e3_id: "1234".to_string(),
+            ciphernode_group_length: 3,
+            ciphernode_threshold: 3,
+            sortition_seed: 1234,
+        };
+        // The id is part of what other processes see, so it must never change between builds
+        assert_eq!(request.id(), EventId(0x9989396657ff0fd8));
+        assert_eq!(request.id(), request.clone().id());
+    }
+
+    #[tokio::test]
+    async fn test_events_over_loopback() -> Result<()> {
+        let rng = Arc::new(Mutex::new(ChaCha20Rng::seed_from_u64(42)));
+        let fhe = Fhe::new(rng, vec![0x3FFFFFFF000001], 2048, 1032193)?;
+        let local = Endpoint::Tcp("127.0.0.1:0".parse().unwrap());
+
+        // The aggregator sends a node its requests and reads back the node's keyshares with its
+        // own parameters
+        let (node, at_node) = Inbox::run();
+        let node = Server::bind(local.clone(), node.recipient()).await?;
+        let (aggregator, at_aggregator) = Inbox::run();
+        let aggregator = Server::bind_with(local, aggregator.recipient(), fhe.params()).await?;
+
+        let request = EnclaveEvent::ComputationRequested {
+            e3_id: "1234".to_string(),
+            ciphernode_group_length: 3,
+            ciphernode_threshold: 3,
+            sortition_seed: 1234,
+        };
+        let keyshare = EnclaveEvent::KeyshareCreated {
+            e3_id: "1234".to_string(),
+            keyshare: fhe.generate_keyshare()?.1,
+        };
+        RemoteAddr::new(node.endpoint().clone())
+            .send(request.clone())
+            .await?;
+        RemoteAddr::new(aggregator.endpoint().clone())
+            .send(keyshare.clone())
+            .await?;
+
+        let at_node = eventually(|| ready(first(&at_node))).await;
+        assert_eq!(at_node.id(), request.id());
+        let at_aggregator = eventually(|| ready(first(&at_aggregator))).await;
+        assert_eq!(at_aggregator.id(), keyshare.id());
+
+        // A keyshare cannot be read back without the parameters
+        let decoded = from_wire::<EnclaveEvent>(&to_wire(&keyshare)?);
+        assert!(matches!(decoded, Err(Error::Wire(_))));
+
+        node.stop();
+        aggregator.stop();
+        Ok(())
+    }
+}
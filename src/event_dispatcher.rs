@@ -1,89 +1,752 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::SystemTime,
+};
+
 use crate::{
-    actor_traits::{run_actor, Actor, ActorSender},
-    ciphernode::Ciphernode,
-    event::EnclaveEvent,
-    logger::Logger,
+    actor_traits::{
+        run_named, Actor, ActorSender, Addr, Context, Handler, MailboxConfig, Message, Overflow,
+        Priority,
+    },
+    dead_letter::{DeadLetter, DeadLetters},
+    error::{Error, Result},
+    event::{EnclaveEvent, EventEnvelope, EventKind},
+    stream::StreamHandle,
 };
 use async_trait::*;
-use tokio::sync::mpsc;
+use futures_core::Stream;
 
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+/// How many events can wait for a listener before the rest go to the dead letters
+const DELIVERY_CAPACITY: usize = 64;
 
-#[derive(Debug, Clone)]
-pub enum Listener {
-    Ciphernode(Ciphernode),
-    Reporter(Logger),
+/// Listener
+/// Anything events can be sent to, such as a `Ciphernode`, a `Logger` or a `RemoteAddr`.
+pub type Listener<E> = Arc<dyn ActorSender<E> + Send + Sync>;
+
+/// SubscriptionId
+/// Identifies one registration of a listener with a dispatcher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// Filter
+/// Which events a listener is sent.
+#[derive(Clone)]
+pub enum Filter {
+    All,
+    Kinds(Vec<EventKind>),
+    Matching(Arc<dyn Fn(&EnclaveEvent) -> bool + Send + Sync>),
 }
 
-#[async_trait]
-impl ActorSender<EnclaveEvent> for Listener {
-    async fn send(&self, event: EnclaveEvent) -> Result<()> {
-        Ok(match self {
-            Listener::Ciphernode(c) => c.send(event).await?,
-            Listener::Reporter(c) => c.send(event).await?,
-        })
+impl Filter {
+    pub fn kinds(kinds: impl IntoIterator<Item = EventKind>) -> Self {
+        Filter::Kinds(kinds.into_iter().collect())
+    }
+
+    pub fn matching<F>(predicate: F) -> Self
+    where
+        F: Fn(&EnclaveEvent) -> bool + Send + Sync + 'static,
+    {
+        Filter::Matching(Arc::new(predicate))
+    }
+
+    /// Only the events of one E3
+    pub fn e3_id(e3_id: impl Into<String>) -> Self {
+        let e3_id = e3_id.into();
+        Filter::matching(move |event| event.e3_id() == e3_id)
+    }
+
+    pub fn matches(&self, event: &EnclaveEvent) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Kinds(kinds) => kinds.contains(&event.kind()),
+            Filter::Matching(predicate) => predicate(event),
+        }
+    }
+}
+
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::All => write!(f, "All"),
+            Filter::Kinds(kinds) => f.debug_tuple("Kinds").field(kinds).finish(),
+            Filter::Matching(_) => write!(f, "Matching(..)"),
+        }
     }
 }
 
+/// StartAt
+/// Where a subscription starts. Earlier events are replayed from the dispatcher's history, ahead
+/// of any live event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartAt {
+    /// Only events dispatched from now on
+    Live,
+    /// Every event still in the history
+    Beginning,
+    /// The event with this sequence number and everything after it, which may not have been
+    /// dispatched yet
+    Seq(u64),
+}
+
 #[async_trait]
-pub trait EventDispatcher<E>: ActorSender<E> + Send + 'static {
-    async fn register(&self, listener: Listener);
+pub trait EventDispatcher<E: 'static>: ActorSender<E> + Send + Sync + 'static {
+    /// Send `listener` every event.
+    async fn register(&self, listener: Listener<EventEnvelope>) -> Result<SubscriptionId> {
+        self.subscribe(listener, Filter::All).await
+    }
+
+    /// Send `listener` only the events that pass `filter`.
+    async fn subscribe(
+        &self,
+        listener: Listener<EventEnvelope>,
+        filter: Filter,
+    ) -> Result<SubscriptionId> {
+        self.subscribe_from(listener, filter, StartAt::Live).await
+    }
+
+    /// As `subscribe`, first catching `listener` up on the events since `start`. Fails with
+    /// `NoHistory` for any start but `Live` if the dispatcher keeps no history.
+    async fn subscribe_from(
+        &self,
+        listener: Listener<EventEnvelope>,
+        filter: Filter,
+        start: StartAt,
+    ) -> Result<SubscriptionId>;
+
+    /// Stop sending events to a listener. Returns `false` if there was no such subscription, for
+    /// example because it was already unregistered. Listeners that stop accepting messages are
+    /// unregistered without this.
+    async fn unregister(&self, id: SubscriptionId) -> Result<bool>;
 }
 
 #[derive(Debug, Clone)]
 pub struct EventBus {
-    sender: mpsc::Sender<EnclaveEvent>,
+    addr: Addr<EventBusActor>,
+    dead_letters: DeadLetters,
+    source: Arc<str>,
 }
 
 impl EventBus {
     pub fn new() -> Self {
-        let actor = EventBusActor::new();
-        let sender = run_actor(actor, 8);
-        EventBus { sender }
+        Self::with_history(0)
+    }
+
+    /// A bus that keeps the last `capacity` events it dispatched to replay to late subscribers.
+    /// Anything older is forgotten, which a subscriber can tell from the gap in sequence numbers.
+    pub fn with_history(capacity: usize) -> Self {
+        let dead_letters = DeadLetters::new();
+        let actor = EventBusActor::new(dead_letters.clone(), capacity);
+        // Registrations jump the queue so a new listener doesn't wait behind a backlog of events
+        let addr = run_named("event bus", actor, MailboxConfig::bounded(8).prioritized());
+        EventBus {
+            addr,
+            dead_letters,
+            source: "anonymous".into(),
+        }
+    }
+
+    /// A handle to the same bus that stamps the events it publishes as coming from `source`.
+    pub fn with_source(&self, source: impl Into<Arc<str>>) -> Self {
+        EventBus {
+            source: source.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Events the bus could not deliver to a listener.
+    pub fn dead_letters(&self) -> &DeadLetters {
+        &self.dead_letters
+    }
+
+    /// Publish every event from `stream` on the bus, for example events read from a chain.
+    pub fn attach_stream<S>(&self, stream: S) -> StreamHandle
+    where
+        S: Stream<Item = EnclaveEvent> + Send + 'static,
+    {
+        self.addr.attach_stream(FromSource {
+            stream: Box::pin(stream),
+            source: self.source.clone(),
+        })
     }
 }
 
 #[async_trait]
 impl EventDispatcher<EnclaveEvent> for EventBus {
-    async fn register(&self, listener: Listener) {
-        let _ = self.send(EnclaveEvent::RegisterListener(listener)).await;
+    async fn subscribe_from(
+        &self,
+        listener: Listener<EventEnvelope>,
+        filter: Filter,
+        start: StartAt,
+    ) -> Result<SubscriptionId> {
+        self.addr
+            .ask(Subscribe {
+                listener,
+                filter,
+                start,
+            })
+            .await
+    }
+
+    async fn unregister(&self, id: SubscriptionId) -> Result<bool> {
+        self.addr.ask(Unsubscribe(id)).await
     }
 }
 
 #[async_trait]
 impl ActorSender<EnclaveEvent> for EventBus {
     async fn send(&self, msg: EnclaveEvent) -> Result<()> {
-        Ok(self.sender.send(msg).await?)
+        self.addr
+            .send(Publish {
+                source: self.source.clone(),
+                event: msg,
+            })
+            .await
+    }
+
+    fn is_alive(&self) -> bool {
+        self.addr.is_alive()
     }
 }
 
+struct Publish {
+    source: Arc<str>,
+    event: EnclaveEvent,
+}
+
+impl Message for Publish {
+    type Response = ();
+}
+
+/// Publishes the events of a stream as coming from `source`
+struct FromSource<S> {
+    stream: Pin<Box<S>>,
+    source: Arc<str>,
+}
+
+impl<S: Stream<Item = EnclaveEvent>> Stream for FromSource<S> {
+    type Item = Publish;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Publish>> {
+        let source = self.source.clone();
+        self.stream
+            .as_mut()
+            .poll_next(cx)
+            .map(|event| event.map(|event| Publish { source, event }))
+    }
+}
+
+struct Subscribe {
+    listener: Listener<EventEnvelope>,
+    filter: Filter,
+    start: StartAt,
+}
+
+impl Message for Subscribe {
+    type Response = SubscriptionId;
+
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+}
+
+struct Unsubscribe(SubscriptionId);
+
+impl Message for Unsubscribe {
+    type Response = bool;
+
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+}
+
+struct Subscription {
+    id: SubscriptionId,
+    listener: Listener<EventEnvelope>,
+    filter: Filter,
+    /// The first sequence number the listener wants
+    from: u64,
+    delivery: Addr<Delivery>,
+}
+
 struct EventBusActor {
-    listeners: Vec<Listener>,
+    subscriptions: Vec<Subscription>,
+    next_id: u64,
+    /// The sequence number of the last event dispatched
+    seq: u64,
+    /// The latest events, oldest first
+    history: VecDeque<EventEnvelope>,
+    history_capacity: usize,
+    dead_letters: DeadLetters,
 }
 
 impl EventBusActor {
-    pub fn new() -> Self {
-        Self { listeners: vec![] }
+    pub fn new(dead_letters: DeadLetters, history_capacity: usize) -> Self {
+        Self {
+            subscriptions: vec![],
+            next_id: 1,
+            seq: 0,
+            history: VecDeque::new(),
+            history_capacity,
+            dead_letters,
+        }
     }
 
-    async fn dispatch(&self, event: EnclaveEvent) -> Result<()> {
-        for listener in self.listeners.iter() {
-            listener.send(event.clone()).await?
+    /// Queue `event` for every listener that wants it. This never waits on a listener.
+    fn dispatch(&mut self, source: Arc<str>, event: EnclaveEvent) {
+        self.prune(|subscription| {
+            !subscription.listener.is_alive() || !subscription.delivery.is_alive()
+        });
+        self.seq += 1;
+        let envelope = EventEnvelope {
+            seq: self.seq,
+            id: event.id(),
+            source,
+            at: SystemTime::now(),
+            event,
+        };
+        // Only listeners that want the event pay for cloning it
+        let wanted = self.subscriptions.iter().filter(|subscription| {
+            envelope.seq >= subscription.from && subscription.filter.matches(&envelope.event)
+        });
+        let mut stopped = vec![];
+        for subscription in wanted {
+            // Fails when the listener is too far behind or once delivery has stopped
+            if let Err(error) = subscription.delivery.try_send(envelope.clone()) {
+                if error == Error::MailboxClosed {
+                    stopped.push(subscription.id);
+                }
+                let _ = self.dead_letters.record(DeadLetter::new(
+                    envelope.clone(),
+                    subscription.id,
+                    subscription.listener.clone(),
+                    error,
+                ));
+            }
         }
+        self.prune(|subscription| stopped.contains(&subscription.id));
+
+        if self.history_capacity > 0 {
+            if self.history.len() == self.history_capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(envelope);
+        }
+    }
+
+    /// Unregister the listeners that have gone away.
+    fn prune(&mut self, dead: impl Fn(&Subscription) -> bool) {
+        self.subscriptions.retain(|subscription| {
+            if dead(subscription) {
+                tracing::warn!(
+                    subscription = ?subscription.id,
+                    "dropping listener as it no longer accepts events"
+                );
+                subscription.delivery.stop();
+                return false;
+            }
+            true
+        });
+    }
+}
+
+impl Actor for EventBusActor {}
+
+#[async_trait]
+impl Handler<Publish> for EventBusActor {
+    async fn handle_message(&mut self, msg: Publish, _: &mut Context<Self>) -> Result<()> {
+        self.dispatch(msg.source, msg.event);
         Ok(())
     }
 }
 
 #[async_trait]
-impl Actor<EnclaveEvent> for EventBusActor {
-    async fn handle_message(&mut self, msg: EnclaveEvent) -> Result<()> {
-        match msg {
-            EnclaveEvent::RegisterListener(listener) => self.listeners.push(listener),
-            other => {
-                let _ = self.dispatch(other).await;
-            }
+impl Handler<Subscribe> for EventBusActor {
+    async fn handle_message(
+        &mut self,
+        msg: Subscribe,
+        ctx: &mut Context<Self>,
+    ) -> Result<SubscriptionId> {
+        if self.history_capacity == 0 && msg.start != StartAt::Live {
+            return Err(Error::NoHistory);
+        }
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        let delivery = Delivery {
+            subscription: id,
+            listener: msg.listener.clone(),
+            dead_letters: self.dead_letters.clone(),
+        };
+        let from = match msg.start {
+            StartAt::Live => self.seq + 1,
+            StartAt::Beginning => 0,
+            StartAt::Seq(seq) => seq,
+        };
+        let replay = self
+            .history
+            .iter()
+            .filter(|envelope| envelope.seq >= from && msg.filter.matches(&envelope.event))
+            .collect::<Vec<_>>();
+
+        // Live events get the usual room on top of whatever is replayed
+        let config =
+            MailboxConfig::bounded(DELIVERY_CAPACITY + replay.len()).overflow(Overflow::Reject);
+        let delivery = ctx.spawn_child(delivery, config);
+        // Queued before any live event can be, as the bus dispatches nothing until this returns
+        for envelope in replay {
+            delivery.try_send(envelope.clone())?;
+        }
+
+        self.subscriptions.push(Subscription {
+            id,
+            listener: msg.listener,
+            filter: msg.filter,
+            from,
+            delivery,
+        });
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl Handler<Unsubscribe> for EventBusActor {
+    async fn handle_message(&mut self, msg: Unsubscribe, _: &mut Context<Self>) -> Result<bool> {
+        let Some(index) = self
+            .subscriptions
+            .iter()
+            .position(|subscription| subscription.id == msg.0)
+        else {
+            return Ok(false);
+        };
+        // Events already queued for the listener are still delivered
+        self.subscriptions.remove(index).delivery.stop();
+        Ok(true)
+    }
+}
+
+/// Delivery
+/// Sends one listener its events in order, so a slow or failing listener only holds up itself.
+/// Events the listener doesn't accept go to the dead letters.
+struct Delivery {
+    subscription: SubscriptionId,
+    listener: Listener<EventEnvelope>,
+    dead_letters: DeadLetters,
+}
+
+impl Actor for Delivery {}
+
+#[async_trait]
+impl Handler<EventEnvelope> for Delivery {
+    async fn handle_message(&mut self, msg: EventEnvelope, ctx: &mut Context<Self>) -> Result<()> {
+        let Err(error) = self.listener.send(msg.clone()).await else {
+            return Ok(());
+        };
+        // The bus prunes the listener when it next dispatches. Whatever is still queued for it
+        // is handled on the way out and ends up here too.
+        if error == Error::MailboxClosed {
+            ctx.stop();
+        }
+        self.dead_letters.record(DeadLetter::new(
+            msg,
+            self.subscription,
+            self.listener.clone(),
+            error,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        actor_traits::run_actor,
+        logger::Logger,
+        remote::{Endpoint, RemoteAddr, Server},
+        sim::Sim,
+        testing::{eventually, wait_until, Inbox},
+    };
+
+    fn requested(e3_id: &str) -> EnclaveEvent {
+        EnclaveEvent::ComputationRequested {
+            e3_id: e3_id.to_string(),
+            ciphernode_group_length: 3,
+            ciphernode_threshold: 3,
+            sortition_seed: 1234,
+        }
+    }
+
+    async fn e3_ids(logger: &Logger) -> Result<Vec<String>> {
+        let log = logger.get_log().await?;
+        Ok(log
+            .iter()
+            .map(|envelope| envelope.event.e3_id().to_string())
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions() -> Result<()> {
+        let sim = Sim::new(0);
+        let bus = EventBus::new();
+        let everything = Logger::new();
+        let only_b = Logger::new();
+        let keyshares = Logger::new();
+        let first = bus.register(Arc::new(everything.clone())).await?;
+        let filter = Filter::matching(|event| event.e3_id() == "b");
+        let second = bus.subscribe(Arc::new(only_b.clone()), filter).await?;
+        assert_ne!(first, second);
+        let filter = Filter::kinds([EventKind::KeyshareCreated]);
+        bus.subscribe(Arc::new(keyshares.clone()), filter).await?;
+
+        bus.send(requested("a")).await?;
+        bus.send(requested("b")).await?;
+        sim.run_until_idle().await;
+
+        assert_eq!(e3_ids(&everything).await?, vec!["a", "b"]);
+        assert_eq!(e3_ids(&only_b).await?, vec!["b"]);
+        assert!(keyshares.get_log().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_envelopes() -> Result<()> {
+        let sim = Sim::new(0);
+        let bus = EventBus::new();
+        let logger = Logger::new();
+        bus.register(Arc::new(logger.clone())).await?;
+
+        bus.with_source("node").send(requested("a")).await?;
+        bus.send(requested("b")).await?;
+        bus.send(requested("a")).await?;
+        sim.run_until_idle().await;
+
+        let log = logger.get_log().await?;
+        let seqs = log.iter().map(|envelope| envelope.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, vec![1, 2, 3]);
+        assert_eq!(&*log[0].source, "node");
+        assert_eq!(&*log[1].source, "anonymous");
+        // The same event twice, as a listener deduplicating by id would see it
+        assert_eq!(log[0].id, log[2].id);
+        assert_ne!(log[0].id, log[1].id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remote_listener() -> Result<()> {
+        let (inbox, received) = Inbox::<EventEnvelope>::run();
+        let local = Endpoint::Tcp("127.0.0.1:0".parse().unwrap());
+        let server = Server::bind(local, inbox.recipient()).await?;
+        let bus = EventBus::new();
+        let remote = RemoteAddr::<EventEnvelope>::new(server.endpoint().clone());
+        bus.register(Arc::new(remote)).await?;
+
+        bus.with_source("aggregator").send(requested("a")).await?;
+        wait_until(|| !received.lock().unwrap().is_empty()).await;
+
+        let envelope = received.lock().unwrap()[0].clone();
+        assert_eq!((envelope.seq, &*envelope.source), (1, "aggregator"));
+        assert_eq!(envelope.id, requested("a").id());
+        assert_eq!(envelope.event.e3_id(), "a");
+        server.stop();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay() -> Result<()> {
+        let sim = Sim::new(0);
+        let bus = EventBus::with_history(3);
+        for e3_id in ["a", "b", "a", "b", "a"] {
+            bus.send(requested(e3_id)).await?;
         }
+        sim.run_until_idle().await;
+
+        let from_beginning = Logger::new();
+        let filter = Filter::e3_id("a");
+        bus.subscribe_from(Arc::new(from_beginning.clone()), filter, StartAt::Beginning)
+            .await?;
+        let from_seq = Logger::new();
+        bus.subscribe_from(Arc::new(from_seq.clone()), Filter::All, StartAt::Seq(4))
+            .await?;
+        let live = Logger::new();
+        bus.register(Arc::new(live.clone())).await?;
+        let ahead = Logger::new();
+        bus.subscribe_from(Arc::new(ahead.clone()), Filter::All, StartAt::Seq(7))
+            .await?;
+        bus.send(requested("a")).await?;
+        bus.send(requested("b")).await?;
+        sim.run_until_idle().await;
+
+        let seqs = |log: Vec<EventEnvelope>| log.iter().map(|e| e.seq).collect::<Vec<_>>();
+        // The first event has dropped out of a history of three
+        assert_eq!(seqs(from_beginning.get_log().await?), vec![3, 5, 6]);
+        assert_eq!(seqs(from_seq.get_log().await?), vec![4, 5, 6, 7]);
+        assert_eq!(seqs(live.get_log().await?), vec![6, 7]);
+        // Live events before the requested start are skipped too
+        assert_eq!(seqs(ahead.get_log().await?), vec![7]);
+
+        // A bus without history has nothing to replay
+        let forgetful = EventBus::new();
+        let late = Arc::new(Logger::new());
+        let replayed = forgetful
+            .subscribe_from(late, Filter::All, StartAt::Beginning)
+            .await;
+        assert_eq!(replayed, Err(Error::NoHistory));
+        Ok(())
+    }
+
+    struct Sink;
+    impl Actor for Sink {}
+
+    #[async_trait]
+    impl Handler<EventEnvelope> for Sink {
+        async fn handle_message(&mut self, _: EventEnvelope, _: &mut Context<Self>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unregister_and_prune() -> Result<()> {
+        let sim = Sim::new(0);
+        let bus = EventBus::new();
+        let stopped = run_actor(Sink, 8);
+        let unregistered = Logger::new();
+        let kept = Logger::new();
+        bus.register(Arc::new(stopped.clone())).await?;
+        let id = bus.register(Arc::new(unregistered.clone())).await?;
+        bus.register(Arc::new(kept.clone())).await?;
+
+        assert!(bus.unregister(id).await?);
+        assert!(!bus.unregister(id).await?);
+        stopped.shutdown().await?;
+        bus.send(requested("a")).await?;
+        sim.run_until_idle().await;
+
+        // The stopped listener no longer holds up the ones after it
+        assert!(unregistered.get_log().await?.is_empty());
+        assert_eq!(e3_ids(&kept).await?, vec!["a"]);
+        Ok(())
+    }
+
+    struct Stall;
+    impl Actor for Stall {}
+
+    #[async_trait]
+    impl Handler<EventEnvelope> for Stall {
+        async fn handle_message(&mut self, _: EventEnvelope, _: &mut Context<Self>) -> Result<()> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_listener_does_not_hold_up_others() -> Result<()> {
+        let bus = EventBus::new();
+        let stalled = run_actor(Stall, 1);
+        let kept = Logger::new();
+        bus.register(Arc::new(stalled)).await?;
+        bus.register(Arc::new(kept.clone())).await?;
+
+        // More events than the stalled listener and the bus have room for between them
+        let sent = (0..20).map(|n| n.to_string()).collect::<Vec<_>>();
+        for e3_id in sent.iter() {
+            bus.send(requested(e3_id)).await?;
+        }
+        let (kept, sent) = (&kept, &sent);
+        let delivered = eventually(|| async move {
+            let delivered = e3_ids(kept).await.unwrap();
+            (delivered.len() == sent.len()).then_some(delivered)
+        })
+        .await;
+        assert_eq!(&delivered, sent);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stuck_listener_overflows_to_dead_letters() -> Result<()> {
+        let bus = EventBus::new();
+        let stalled = run_actor(Stall, 1);
+        let id = bus.register(Arc::new(stalled)).await?;
+
+        // The listener holds one event, its mailbox another and its delivery one more that it is
+        // waiting to hand over
+        let sent = DELIVERY_CAPACITY + 10;
+        for n in 0..sent {
+            bus.send(requested(&n.to_string())).await?;
+        }
+        let bus = &bus;
+        let letters = eventually(|| async move {
+            let letters = bus.dead_letters().list().await.unwrap();
+            (letters.len() >= sent - DELIVERY_CAPACITY - 3).then_some(letters)
+        })
+        .await;
+        assert!(letters
+            .iter()
+            .all(|letter| letter.subscription == id && letter.error == Error::MailboxFull));
+        // Falling behind doesn't cost the listener its subscription
+        assert!(bus.unregister(id).await?);
+        Ok(())
+    }
+
+    /// Notes each event it starts on, then waits for a permit from the gate to finish it
+    struct Gated {
+        gate: Arc<tokio::sync::Semaphore>,
+        started: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+    impl Actor for Gated {}
+
+    #[async_trait]
+    impl Handler<EventEnvelope> for Gated {
+        async fn handle_message(
+            &mut self,
+            msg: EventEnvelope,
+            _: &mut Context<Self>,
+        ) -> Result<()> {
+            self.started
+                .lock()
+                .unwrap()
+                .push(msg.event.e3_id().to_string());
+            self.gate.acquire().await.unwrap().forget();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() -> Result<()> {
+        let bus = EventBus::new();
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let started = Arc::new(std::sync::Mutex::new(vec![]));
+        let gated = Gated {
+            gate: gate.clone(),
+            started: started.clone(),
+        };
+        let gated = run_actor(gated, MailboxConfig::bounded(1).overflow(Overflow::Reject));
+        let id = bus.register(Arc::new(gated.clone())).await?;
+
+        // One event being handled, one queued and one with nowhere to go
+        bus.send(requested("1")).await?;
+        wait_until(|| started.lock().unwrap().len() == 1).await;
+        bus.send(requested("2")).await?;
+        bus.send(requested("3")).await?;
+        let letters = eventually(|| async {
+            let letters = bus.dead_letters().list().await.unwrap();
+            (!letters.is_empty()).then_some(letters)
+        })
+        .await;
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].envelope.event.e3_id(), "3");
+        assert_eq!(letters[0].subscription, id);
+        assert_eq!(letters[0].error, Error::MailboxFull);
+
+        gate.add_permits(3);
+        wait_until(|| started.lock().unwrap().len() == 2).await;
+        assert_eq!(bus.dead_letters().redrive().await?, 1);
+        assert!(bus.dead_letters().list().await?.is_empty());
+        wait_until(|| started.lock().unwrap().len() == 3).await;
+        assert_eq!(*started.lock().unwrap(), vec!["1", "2", "3"]);
         Ok(())
     }
 }
@@ -9,9 +9,14 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-// Some loose error/result stuff we can use for this module
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+use crate::error::{Error, Result};
+
+// Errors from fhe.rs are not leaked beyond this module
+impl From<fhe::Error> for Error {
+    fn from(e: fhe::Error) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
 
 // Define a trait for Rng which we use below
 pub trait Rng: RngCore + CryptoRng + Send + 'static {}
@@ -103,6 +108,14 @@ impl<R: Rng> Fhe<R> {
         (&self.params, &self.crp)
     }
 
+    /// What another process needs to read back the keyshares this creates
+    pub fn params(&self) -> FheParams {
+        FheParams {
+            params: self.params.clone(),
+            crp: self.crp.clone(),
+        }
+    }
+
     pub fn generate_keyshare(&self) -> Result<(SecretKey, PublicKeyShare)> {
         let sk_share = {
             let mut r1 = self.rng.lock().unwrap();
@@ -115,3 +128,18 @@ impl<R: Rng> Fhe<R> {
         Ok((SecretKey(sk_share), PublicKeyShare(pk_share)))
     }
 }
+
+/// FheParams
+/// The parameters keyshares are created with, for reading back keyshares that arrive as bytes.
+#[derive(Clone)]
+pub struct FheParams {
+    params: Arc<BfvParameters>,
+    crp: CommonRandomPoly,
+}
+
+impl FheParams {
+    pub fn keyshare_from_bytes(&self, bytes: &[u8]) -> Result<PublicKeyShare> {
+        let share = FheRsPublicKeyShare::deserialize(bytes, &self.params, self.crp.clone())?;
+        Ok(PublicKeyShare(share))
+    }
+}
@@ -1,44 +1,48 @@
 use async_trait::async_trait;
-use tokio::sync::{mpsc, oneshot};
 
-use crate::{actor_traits::*, event::EnclaveEvent};
-
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+use crate::{actor_traits::*, error::Result, event::EventEnvelope};
 
+/// Ask the logger for every event it has recorded so far
 #[derive(Debug)]
-pub enum LogEvent {
-    Log(EnclaveEvent),
-    GetLog(oneshot::Sender<Vec<EnclaveEvent>>),
+pub struct GetLog;
+
+impl Message for GetLog {
+    type Response = Vec<EventEnvelope>;
 }
 
 #[derive(Debug, Clone)]
 pub struct Logger {
-    sender: mpsc::Sender<LogEvent>,
+    addr: Addr<LoggerActor>,
 }
 
 impl Logger {
     pub fn new() -> Self {
         let actor = LoggerActor::new();
-        let sender = run_actor(actor, 8);
-        Logger { sender }
+        // A slow logger should lose old entries rather than hold up whoever is reporting to it
+        let addr = run_actor(
+            actor,
+            MailboxConfig::bounded(64).overflow(Overflow::DropOldest),
+        );
+        Logger { addr }
     }
-    pub async fn get_log(&self) -> Result<Vec<EnclaveEvent>> {
-        let (send, recv) = oneshot::channel();
-        let _ = self.sender.send(LogEvent::GetLog(send)).await;
-        Ok(recv.await?)
+    pub async fn get_log(&self) -> Result<Vec<EventEnvelope>> {
+        self.addr.ask(GetLog).await
     }
 }
 
 #[async_trait]
-impl ActorSender<EnclaveEvent> for Logger {
-    async fn send(&self, msg: EnclaveEvent) -> Result<()> {
-        Ok(self.sender.send(LogEvent::Log(msg)).await?)
+impl ActorSender<EventEnvelope> for Logger {
+    async fn send(&self, msg: EventEnvelope) -> Result<()> {
+        self.addr.send(msg).await
+    }
+
+    fn is_alive(&self) -> bool {
+        self.addr.is_alive()
     }
 }
 
 struct LoggerActor {
-    log: Vec<EnclaveEvent>,
+    log: Vec<EventEnvelope>,
 }
 
 impl LoggerActor {
@@ -47,15 +51,23 @@ impl LoggerActor {
     }
 }
 
+impl Actor for LoggerActor {}
+
 #[async_trait]
-impl Actor<LogEvent> for LoggerActor {
-    async fn handle_message(&mut self, msg: LogEvent) -> Result<()> {
-        match msg {
-            LogEvent::Log(log_msg) => self.log.push(log_msg),
-            LogEvent::GetLog(reply) => {
-                let _ = reply.send(self.log.clone());
-            }
-        }
+impl Handler<EventEnvelope> for LoggerActor {
+    async fn handle_message(&mut self, msg: EventEnvelope, _: &mut Context<Self>) -> Result<()> {
+        self.log.push(msg);
         Ok(())
     }
 }
+
+#[async_trait]
+impl Handler<GetLog> for LoggerActor {
+    async fn handle_message(
+        &mut self,
+        _: GetLog,
+        _: &mut Context<Self>,
+    ) -> Result<Vec<EventEnvelope>> {
+        Ok(self.log.clone())
+    }
+}
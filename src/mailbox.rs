@@ -0,0 +1,496 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    pin::pin,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+use crate::error::Error;
+
+/// Overflow
+/// What a bounded mailbox does with a message that arrives while it is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Wait for room. `try_send` fails instead of waiting.
+    Block,
+    /// Wait for room, but no longer than the given duration
+    BlockWithTimeout(Duration),
+    /// Throw the incoming message away
+    DropNewest,
+    /// Throw the oldest queued message away to make room
+    DropOldest,
+    /// Fail the send with `MailboxFull`
+    Reject,
+}
+
+/// Priority
+/// The lane a message is queued in when its actor has a prioritized mailbox. Lanes are emptied in
+/// order, so a `System` message overtakes everything queued in the other two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Lifecycle and control messages. These are never held back by a full mailbox.
+    System,
+    /// Messages that should not wait behind a backlog, such as registrations
+    High,
+    Normal,
+}
+
+impl Priority {
+    const LANES: usize = 3;
+
+    fn lane(self) -> usize {
+        self as usize
+    }
+}
+
+/// Anything that can be queued in a mailbox
+pub(crate) trait Prioritized {
+    fn priority(&self) -> Priority;
+}
+
+/// MailboxConfig
+/// Capacity and overflow policy of an actor's mailbox. A plain `usize` converts into a bounded
+/// mailbox that blocks when full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MailboxConfig {
+    capacity: Option<usize>,
+    overflow: Overflow,
+    prioritized: bool,
+}
+
+impl MailboxConfig {
+    /// Panics if `capacity` is 0, as no message could ever be queued.
+    pub fn bounded(capacity: usize) -> Self {
+        assert!(capacity > 0, "mailbox capacity must be greater than 0");
+        Self {
+            capacity: Some(capacity),
+            overflow: Overflow::Block,
+            prioritized: false,
+        }
+    }
+
+    pub fn unbounded() -> Self {
+        Self {
+            capacity: None,
+            overflow: Overflow::Block,
+            prioritized: false,
+        }
+    }
+
+    /// Only meaningful for bounded mailboxes
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Queue messages by their `Message::priority` instead of strictly first in first out.
+    /// `DropOldest` then drops from the lowest priority lane first.
+    pub fn prioritized(mut self) -> Self {
+        self.prioritized = true;
+        self
+    }
+}
+
+impl From<usize> for MailboxConfig {
+    fn from(capacity: usize) -> Self {
+        Self::bounded(capacity)
+    }
+}
+
+struct State<T> {
+    lanes: [VecDeque<T>; Priority::LANES],
+    senders: usize,
+    closed: bool,
+}
+
+impl<T> State<T> {
+    fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    fn pop_front(&mut self) -> Option<T> {
+        self.lanes.iter_mut().find_map(VecDeque::pop_front)
+    }
+
+    /// The oldest message in the lowest priority lane that has any, leaving system messages be
+    fn pop_oldest(&mut self) -> Option<T> {
+        self.lanes[Priority::High.lane()..]
+            .iter_mut()
+            .rev()
+            .find_map(VecDeque::pop_front)
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    config: MailboxConfig,
+    /// Wakes the receiver when a message arrives or the last sender goes
+    received: Notify,
+    /// Wakes blocked senders when a message is taken or the mailbox closes
+    space: Notify,
+}
+
+impl<T> Shared<T> {
+    fn state(&self) -> std::sync::MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+
+    async fn closed(&self) {
+        loop {
+            let mut space = pin!(self.space.notified());
+            space.as_mut().enable();
+            if self.state().closed {
+                return;
+            }
+            space.await;
+        }
+    }
+}
+
+impl<T: Prioritized> Shared<T> {
+    /// Queue `item` if the policy allows it without waiting. Hands the item back when it could
+    /// not be queued so a blocking sender can try again.
+    fn push(&self, item: T) -> Result<(), (T, Error)> {
+        let mut state = self.state();
+        if state.closed {
+            return Err((item, Error::MailboxClosed));
+        }
+        let priority = match self.config.prioritized {
+            true => item.priority(),
+            false => Priority::Normal,
+        };
+        let full = self
+            .config
+            .capacity
+            .is_some_and(|capacity| state.len() >= capacity);
+        // Messages thrown away are only dropped once the lock is released, as one may hold a
+        // sender to this very mailbox
+        let mut evicted = None;
+        if full && priority != Priority::System {
+            match self.config.overflow {
+                Overflow::Block | Overflow::BlockWithTimeout(_) | Overflow::Reject => {
+                    return Err((item, Error::MailboxFull))
+                }
+                Overflow::DropNewest => {
+                    drop(state);
+                    drop(item);
+                    return Ok(());
+                }
+                Overflow::DropOldest => evicted = state.pop_oldest(),
+            }
+        }
+        state.lanes[priority.lane()].push_back(item);
+        drop(state);
+        drop(evicted);
+        self.received.notify_one();
+        Ok(())
+    }
+}
+
+/// Create a mailbox along with the sender half that feeds it.
+pub(crate) fn mailbox<T>(config: MailboxConfig) -> (MailboxSender<T>, MailboxReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            lanes: Default::default(),
+            senders: 1,
+            closed: false,
+        }),
+        config,
+        received: Notify::new(),
+        space: Notify::new(),
+    });
+    (
+        MailboxSender {
+            shared: shared.clone(),
+        },
+        MailboxReceiver { shared },
+    )
+}
+
+pub(crate) struct MailboxSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Prioritized> MailboxSender<T> {
+    pub(crate) async fn send(&self, item: T) -> Result<(), Error> {
+        match self.shared.config.overflow {
+            Overflow::Block => self.send_blocking(item).await,
+            Overflow::BlockWithTimeout(timeout) => {
+                tokio::time::timeout(timeout, self.send_blocking(item))
+                    .await
+                    .unwrap_or(Err(Error::MailboxFull))
+            }
+            _ => self.try_send(item),
+        }
+    }
+
+    pub(crate) fn try_send(&self, item: T) -> Result<(), Error> {
+        self.shared.push(item).map_err(|(_, e)| e)
+    }
+
+    async fn send_blocking(&self, mut item: T) -> Result<(), Error> {
+        loop {
+            let mut space = pin!(self.shared.space.notified());
+            space.as_mut().enable();
+            item = match self.shared.push(item) {
+                Ok(()) => return Ok(()),
+                Err((item, Error::MailboxFull)) => item,
+                Err((_, e)) => return Err(e),
+            };
+            space.await;
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.shared.state().closed
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shared.state().len()
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakMailboxSender<T> {
+        WeakMailboxSender {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+}
+
+impl<T> Clone for MailboxSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for MailboxSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.received.notify_one();
+        }
+    }
+}
+
+impl<T> fmt::Debug for MailboxSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MailboxSender")
+            .field("config", &self.shared.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A sender that does not keep the mailbox open on its own.
+pub(crate) struct WeakMailboxSender<T> {
+    shared: Weak<Shared<T>>,
+}
+
+impl<T> WeakMailboxSender<T> {
+    pub(crate) fn upgrade(&self) -> Option<MailboxSender<T>> {
+        let shared = self.shared.upgrade()?;
+        let mut state = shared.state();
+        if state.senders == 0 {
+            return None;
+        }
+        state.senders += 1;
+        drop(state);
+        Some(MailboxSender { shared })
+    }
+
+    /// How many messages are queued, zero once the mailbox is gone.
+    pub(crate) fn len(&self) -> usize {
+        self.shared
+            .upgrade()
+            .map_or(0, |shared| shared.state().len())
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.shared
+            .upgrade()
+            .is_none_or(|shared| shared.state().closed)
+    }
+
+    /// Resolves once the mailbox stops accepting messages, without keeping it open meanwhile.
+    pub(crate) async fn closed(&self) {
+        if let Some(shared) = self.shared.upgrade() {
+            shared.closed().await
+        }
+    }
+}
+
+impl<T> Clone for WeakMailboxSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+pub(crate) struct MailboxReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> MailboxReceiver<T> {
+    /// The next message, or `None` once the mailbox is empty and either closed or without senders.
+    pub(crate) async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.state();
+                if let Some(item) = state.pop_front() {
+                    drop(state);
+                    self.shared.space.notify_waiters();
+                    return Some(item);
+                }
+                if state.closed || state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.received.notified().await;
+        }
+    }
+
+    /// Stop accepting messages. Those already queued can still be received.
+    pub(crate) fn close(&mut self) {
+        self.shared.state().closed = true;
+        self.shared.space.notify_waiters();
+    }
+}
+
+impl<T> Drop for MailboxReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state();
+        state.closed = true;
+        // Dropped outside the lock for the same reason as in `push`
+        let queued = std::mem::take(&mut state.lanes);
+        drop(state);
+        drop(queued);
+        self.shared.space.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Prioritized for u32 {
+        fn priority(&self) -> Priority {
+            Priority::Normal
+        }
+    }
+
+    impl Prioritized for (Priority, u32) {
+        fn priority(&self) -> Priority {
+            self.0
+        }
+    }
+
+    fn full_mailbox(overflow: Overflow) -> (MailboxSender<u32>, MailboxReceiver<u32>) {
+        let (sender, receiver) = mailbox(MailboxConfig::bounded(2).overflow(overflow));
+        sender.try_send(1).unwrap();
+        sender.try_send(2).unwrap();
+        (sender, receiver)
+    }
+
+    async fn drain<T: Prioritized>(
+        sender: MailboxSender<T>,
+        mut receiver: MailboxReceiver<T>,
+    ) -> Vec<T> {
+        drop(sender);
+        let mut items = vec![];
+        while let Some(item) = receiver.recv().await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn test_overflow() {
+        let (sender, receiver) = full_mailbox(Overflow::DropNewest);
+        assert_eq!(sender.send(3).await, Ok(()));
+        assert_eq!(drain(sender, receiver).await, vec![1, 2]);
+
+        let (sender, receiver) = full_mailbox(Overflow::DropOldest);
+        assert_eq!(sender.send(3).await, Ok(()));
+        assert_eq!(drain(sender, receiver).await, vec![2, 3]);
+
+        let (sender, receiver) = full_mailbox(Overflow::Reject);
+        assert_eq!(sender.send(3).await, Err(Error::MailboxFull));
+        assert_eq!(drain(sender, receiver).await, vec![1, 2]);
+
+        let timeout = Duration::from_millis(10);
+        let (sender, mut receiver) = full_mailbox(Overflow::BlockWithTimeout(timeout));
+        assert_eq!(sender.send(3).await, Err(Error::MailboxFull));
+        let (sent, received) = tokio::join!(sender.send(3), receiver.recv());
+        assert_eq!((sent, received), (Ok(()), Some(1)));
+        assert_eq!(drain(sender, receiver).await, vec![2, 3]);
+
+        let (sender, mut receiver) = full_mailbox(Overflow::Block);
+        receiver.close();
+        assert_eq!(sender.send(3).await, Err(Error::MailboxClosed));
+    }
+
+    #[test]
+    #[should_panic(expected = "mailbox capacity must be greater than 0")]
+    fn test_zero_capacity() {
+        MailboxConfig::bounded(0);
+    }
+
+    #[tokio::test]
+    async fn test_priority() {
+        let config = MailboxConfig::bounded(2)
+            .overflow(Overflow::DropOldest)
+            .prioritized();
+        let (sender, receiver) = mailbox(config);
+        for msg in [
+            (Priority::Normal, 1),
+            (Priority::Normal, 2),
+            (Priority::High, 3),
+            (Priority::System, 4),
+        ] {
+            sender.try_send(msg).unwrap();
+        }
+
+        // 1 made room for 3, while 4 is let in over capacity
+        assert_eq!(
+            drain(sender, receiver).await,
+            vec![
+                (Priority::System, 4),
+                (Priority::High, 3),
+                (Priority::Normal, 2)
+            ]
+        );
+    }
+
+    /// A message carrying a sender back to its own mailbox, like a reply-to address
+    struct ReplyTo(Option<MailboxSender<ReplyTo>>);
+
+    impl Prioritized for ReplyTo {
+        fn priority(&self) -> Priority {
+            Priority::Normal
+        }
+    }
+
+    #[test]
+    fn test_drop_queued_sender() {
+        let (done, finished) = std::sync::mpsc::channel();
+        // Run apart so a deadlock fails the test rather than hanging it
+        std::thread::spawn(move || {
+            let config = MailboxConfig::bounded(1).overflow(Overflow::DropOldest);
+            let (sender, receiver) = mailbox(config);
+            sender.try_send(ReplyTo(Some(sender.clone()))).unwrap();
+            sender.try_send(ReplyTo(Some(sender.clone()))).unwrap();
+            drop(receiver);
+            done.send(()).unwrap();
+        });
+        finished
+            .recv_timeout(Duration::from_secs(5))
+            .expect("queued senders are dropped without deadlocking");
+    }
+}
@@ -1,17 +1,28 @@
 mod actor_traits;
 mod ciphernode;
+mod context;
+mod dead_letter;
 mod encryptor;
+mod error;
 mod event;
 mod event_dispatcher;
 mod fhe;
 mod logger;
+mod mailbox;
+mod registry;
+mod remote;
+// Only tests run in a simulation, so the rest of the crate pays nothing for its hooks
+#[cfg(test)]
+mod sim;
 mod store;
+mod stream;
+mod supervisor;
+#[cfg(test)]
+mod testing;
+mod trace;
 // mod usecases;
 
-use actor_traits::*;
-
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+use error::Result;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -20,68 +31,71 @@ async fn main() -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use std::{
-        sync::{Arc, Mutex},
-        time::Duration,
-    };
+    use std::sync::{Arc, Mutex};
 
-    use rand::{rngs::OsRng, SeedableRng};
+    use rand::SeedableRng;
     use rand_chacha::ChaCha20Rng;
-    use tokio::time::sleep;
 
     use crate::{
         actor_traits::*,
         ciphernode::Ciphernode,
         encryptor::AesEncryptor,
-        event::EnclaveEvent,
-        event_dispatcher::{EventBus, EventDispatcher, Listener},
+        error::Result,
+        event::{EnclaveEvent, EventKind},
+        event_dispatcher::{EventBus, EventDispatcher, Filter},
         fhe::Fhe,
         logger::Logger,
+        registry,
+        sim::Sim,
         store::DataStore,
     };
 
-    type Error = Box<dyn std::error::Error>;
-    type Result<T> = std::result::Result<T, Error>;
+    fn seeded_fhe() -> Result<Fhe<ChaCha20Rng>> {
+        Fhe::new(
+            Arc::new(Mutex::new(ChaCha20Rng::seed_from_u64(42))),
+            vec![0x3FFFFFFF000001],
+            2048,
+            1032193,
+        )
+    }
 
     #[tokio::test]
     async fn test_main() -> Result<()> {
+        let sim = Sim::new(42);
         let dispatcher = EventBus::new();
         let store = DataStore::new();
         let key = b"a 32-byte secret key here!!!!!!!".to_vec();
         let encryptor = AesEncryptor::new(key);
-        let fhe = Fhe::new(
-            Arc::new(Mutex::new(ChaCha20Rng::seed_from_u64(42))),
-            vec![0x3FFFFFFF000001],
-            2048,
-            1032193,
-        )?;
+        let fhe = seeded_fhe()?;
 
         let ciphernode1 = Ciphernode::new(
-            dispatcher.clone(),
+            "ciphernode 1",
+            dispatcher.with_source("ciphernode 1"),
             store.clone(),
             fhe.clone(),
             encryptor.clone(),
         );
         let ciphernode2 = Ciphernode::new(
-            dispatcher.clone(),
+            "ciphernode 2",
+            dispatcher.with_source("ciphernode 2"),
             store.clone(),
             fhe.clone(),
             encryptor.clone(),
         );
         let ciphernode3 = Ciphernode::new(
-            dispatcher.clone(),
+            "ciphernode 3",
+            dispatcher.with_source("ciphernode 3"),
             store.clone(),
             fhe.clone(),
             encryptor.clone(),
         );
         let reporter = Logger::new();
 
-        dispatcher
-            .register(Listener::Reporter(reporter.clone()))
-            .await;
-        dispatcher.register(Listener::Ciphernode(ciphernode1)).await;
-        dispatcher.register(Listener::Ciphernode(ciphernode2)).await;
-        dispatcher.register(Listener::Ciphernode(ciphernode3)).await;
+        dispatcher.register(Arc::new(reporter.clone())).await?;
+        for ciphernode in [ciphernode1, ciphernode2, ciphernode3] {
+            let requests = Filter::kinds([EventKind::ComputationRequested]);
+            dispatcher.subscribe(Arc::new(ciphernode), requests).await?;
+        }
         dispatcher
             .send(EnclaveEvent::ComputationRequested {
                 e3_id: "1234".to_string(),
@@ -90,32 +104,64 @@ mod tests {
                 sortition_seed: 1234,
             })
             .await?;
-        sleep(Duration::from_millis(0)).await;
+        sim.run_until_idle().await;
+
+        let names = registry::actors()
+            .into_iter()
+            .map(|stats| stats.name)
+            .collect::<Vec<_>>();
+        for name in [
+            "ciphernode 1",
+            "ciphernode 2",
+            "ciphernode 3",
+            "store",
+            "event bus",
+        ] {
+            assert!(
+                names.iter().any(|listed| listed == name),
+                "{} is listed",
+                name
+            );
+        }
 
         let log = reporter.get_log().await?;
-        let expected = vec![
-            EnclaveEvent::ComputationRequested {
-                e3_id: "1234".to_owned(),
-                ciphernode_group_length: 3,
-                ciphernode_threshold: 3,
-                sortition_seed: 1234,
-            },
-            // TODO: get correct test data....
-            // EnclaveEvent::KeyshareCreated {
-            //     e3_id: "1234".to_owned(),
-            //     keyshare: "Hello World".to_owned(),
-            // },
-            // EnclaveEvent::KeyshareCreated {
-            //     e3_id: "1234".to_owned(),
-            //     keyshare: "Hello World".to_owned(),
-            // },
-            // EnclaveEvent::KeyshareCreated {
-            //     e3_id: "1234".to_owned(),
-            //     keyshare: "Hello World".to_owned(),
-            // },
-        ];
+        assert_eq!(log.len(), 4);
+        let seqs = log.iter().map(|envelope| envelope.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, vec![1, 2, 3, 4]);
+        assert_eq!(
+            format!("{:?}", log[0].event),
+            format!(
+                "{:?}",
+                EnclaveEvent::ComputationRequested {
+                    e3_id: "1234".to_owned(),
+                    ciphernode_group_length: 3,
+                    ciphernode_threshold: 3,
+                    sortition_seed: 1234,
+                }
+            )
+        );
 
-        assert_eq!(format!("{:?}", log), format!("{:?}", expected));
+        // The seed fixes the order the nodes run in, and as they share one seeded rng they create
+        // the same keyshares as a fresh copy of it would, in that order
+        let mut keyshares = vec![];
+        let mut sources = vec![];
+        for envelope in &log[1..] {
+            sources.push(envelope.source.to_string());
+            match &envelope.event {
+                EnclaveEvent::KeyshareCreated { e3_id, keyshare } => {
+                    assert_eq!(e3_id, "1234");
+                    keyshares.push(keyshare.as_bytes());
+                }
+                other => panic!("expected a keyshare, got {:?}", other),
+            }
+        }
+        let reference = seeded_fhe()?;
+        let mut expected = vec![];
+        for _ in 0..3 {
+            expected.push(reference.generate_keyshare()?.1.as_bytes());
+        }
+        assert_eq!(keyshares, expected);
+        assert_eq!(sources, ["ciphernode 1", "ciphernode 2", "ciphernode 3"]);
 
         Ok(())
     }
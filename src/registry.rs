@@ -0,0 +1,243 @@
+use std::{
+    any::type_name,
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{
+    actor_traits::{Actor, Envelope},
+    mailbox::WeakMailboxSender,
+};
+
+/// Upper bounds of the handler latency buckets. Anything slower lands in one last bucket.
+pub const LATENCY_BUCKETS: [Duration; 5] = [
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+static REGISTRY: Mutex<BTreeMap<u64, Entry>> = Mutex::new(BTreeMap::new());
+
+/// ActorStats
+/// A snapshot of one running actor as listed by `actors`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActorStats {
+    /// Unique among the actors started by this process
+    pub id: u64,
+    /// The name the actor was started with, or its type if it wasn't given one
+    pub name: String,
+    /// Whether the actor still accepts messages
+    pub alive: bool,
+    pub mailbox_len: usize,
+    /// Messages handled, including those that failed
+    pub processed: u64,
+    /// Messages whose handler returned an error or panicked
+    pub failed: u64,
+    /// How many handlers took at most each of `LATENCY_BUCKETS`, with the slower ones last
+    pub latency: [u64; LATENCY_BUCKETS.len() + 1],
+    /// How long the handler running right now has been at it
+    pub busy_for: Option<Duration>,
+}
+
+/// Every actor currently running, oldest first.
+pub fn actors() -> Vec<ActorStats> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, entry)| entry.stats(*id))
+        .collect()
+}
+
+/// Metrics
+/// Counters an actor's context updates as it handles messages.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    processed: AtomicU64,
+    failed: AtomicU64,
+    latency: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    handling_since: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    pub(crate) fn handling(&self) -> Instant {
+        let now = Instant::now();
+        *self.handling_since.lock().unwrap() = Some(now);
+        now
+    }
+
+    pub(crate) fn is_handling(&self) -> bool {
+        self.handling_since.lock().unwrap().is_some()
+    }
+
+    pub(crate) fn handled(&self, started: Instant, ok: bool) {
+        let elapsed = started.elapsed();
+        *self.handling_since.lock().unwrap() = None;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| elapsed <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The mailbox of an actor of any type
+trait Probe: Send {
+    fn mailbox_len(&self) -> usize;
+    fn is_alive(&self) -> bool;
+}
+
+impl<A: Actor> Probe for WeakMailboxSender<Box<dyn Envelope<A>>> {
+    fn mailbox_len(&self) -> usize {
+        self.len()
+    }
+
+    fn is_alive(&self) -> bool {
+        !self.is_closed()
+    }
+}
+
+struct Entry {
+    name: String,
+    metrics: Arc<Metrics>,
+    probe: Box<dyn Probe>,
+}
+
+impl Entry {
+    fn stats(&self, id: u64) -> ActorStats {
+        let metrics = &self.metrics;
+        ActorStats {
+            id,
+            name: self.name.clone(),
+            alive: self.probe.is_alive(),
+            mailbox_len: self.probe.mailbox_len(),
+            processed: metrics.processed.load(Ordering::Relaxed),
+            failed: metrics.failed.load(Ordering::Relaxed),
+            latency: metrics
+                .latency
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            busy_for: metrics
+                .handling_since
+                .lock()
+                .unwrap()
+                .map(|since| since.elapsed()),
+        }
+    }
+}
+
+/// Registration
+/// Keeps an actor listed in the registry for as long as its task runs.
+pub(crate) struct Registration {
+    id: u64,
+    metrics: Arc<Metrics>,
+}
+
+impl Registration {
+    pub(crate) fn new<A: Actor>(
+        name: Option<String>,
+        sender: WeakMailboxSender<Box<dyn Envelope<A>>>,
+    ) -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        let metrics = Arc::new(Metrics::default());
+        REGISTRY.lock().unwrap().insert(
+            id,
+            Entry {
+                name: name.unwrap_or_else(|| type_name::<A>().to_string()),
+                metrics: metrics.clone(),
+                probe: Box::new(sender),
+            },
+        );
+        Self { id, metrics }
+    }
+
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Run `task`, the actor's task, unlisting the actor once it is done.
+    pub(crate) async fn hold<F: Future>(self, task: F) -> F::Output {
+        task.await
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        REGISTRY.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        actor_traits::{run_actor, run_named, Context, Handler, Message},
+        error::{Error, Result},
+        sim::Sim,
+    };
+
+    struct Worker;
+    impl Actor for Worker {}
+
+    /// Takes the given number of milliseconds, then fails if asked to
+    struct Work(u64, bool);
+    impl Message for Work {
+        type Response = ();
+    }
+
+    #[async_trait]
+    impl Handler<Work> for Worker {
+        async fn handle_message(&mut self, msg: Work, _: &mut Context<Self>) -> Result<()> {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+            match msg.1 {
+                true => Err(Error::InvalidEvent("told to fail".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn stats_of(name: &str) -> Option<ActorStats> {
+        actors().into_iter().find(|stats| stats.name == name)
+    }
+
+    #[tokio::test]
+    async fn test_registry() {
+        let sim = Sim::new(0);
+        let worker = run_actor(Worker, 8);
+        let named = run_named("worker 2", Worker, 8);
+        for work in [Work(5, false), Work(50, false), Work(0, true)] {
+            worker.send(work).await.unwrap();
+        }
+        sim.advance(Duration::from_secs(1)).await;
+
+        let stats = stats_of(type_name::<Worker>()).unwrap();
+        assert!(stats.alive);
+        assert_eq!(stats.mailbox_len, 0);
+        assert_eq!((stats.processed, stats.failed), (3, 1));
+        assert_eq!(stats.latency, [1, 1, 1, 0, 0, 0]);
+        assert_eq!(stats.busy_for, None);
+
+        assert_eq!(stats_of("worker 2").unwrap().processed, 0);
+
+        worker.shutdown().await.unwrap();
+        assert_eq!(stats_of(type_name::<Worker>()), None);
+        named.shutdown().await.unwrap();
+        assert_eq!(stats_of("worker 2"), None);
+    }
+}
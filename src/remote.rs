@@ -0,0 +1,669 @@
+use std::{
+    any::{type_name, Any},
+    fmt, io,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::{AbortHandle, JoinSet},
+    time::sleep,
+};
+
+use crate::{
+    actor_traits::{run_actor, Actor, ActorSender, Addr, Context, Handler, Message, Recipient},
+    error::{Error, Result},
+};
+
+/// Frames larger than this are refused rather than buffered
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// How long a server waits before accepting again after a failed accept
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Wire
+/// A message that can be sent to an actor in another process. Implementations write their fields
+/// in order with the `Wire` impls of the field types and read them back in the same order. Fields
+/// that can only be read back with local knowledge, such as keys and their parameters, get it from
+/// `WireReader::context`.
+pub trait Wire: Sized {
+    fn encode(&self, out: &mut WireWriter) -> Result<()>;
+    fn decode(input: &mut WireReader<'_>) -> Result<Self>;
+}
+
+/// Encode `msg` into the payload of a single frame.
+pub fn to_wire<M: Wire>(msg: &M) -> Result<Vec<u8>> {
+    let mut out = WireWriter(vec![]);
+    msg.encode(&mut out)?;
+    Ok(out.0)
+}
+
+/// Decode a message from the payload of a single frame, which it must use up entirely.
+pub fn from_wire<M: Wire>(bytes: &[u8]) -> Result<M> {
+    decode_frame(bytes, None)
+}
+
+fn decode_frame<M: Wire>(bytes: &[u8], context: Option<&(dyn Any + Send + Sync)>) -> Result<M> {
+    let mut input = WireReader { bytes, context };
+    let msg = M::decode(&mut input)?;
+    match input.bytes.len() {
+        0 => Ok(msg),
+        left => Err(Error::Wire(format!(
+            "{} bytes left over after decoding",
+            left
+        ))),
+    }
+}
+
+pub struct WireWriter(Vec<u8>);
+
+impl WireWriter {
+    pub fn put(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+pub struct WireReader<'a> {
+    bytes: &'a [u8],
+    context: Option<&'a (dyn Any + Send + Sync)>,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::Wire("frame ended early".to_string()));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// What the receiving side passed in to decode with, see `Server::bind_with`.
+    pub fn context<T: Any>(&self) -> Result<&'a T> {
+        self.context
+            .and_then(|context| context.downcast_ref())
+            .ok_or_else(|| Error::Wire(format!("decoding needs a {}", type_name::<T>())))
+    }
+}
+
+impl Wire for u8 {
+    fn encode(&self, out: &mut WireWriter) -> Result<()> {
+        out.put(&[*self]);
+        Ok(())
+    }
+
+    fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+        Ok(input.take(1)?[0])
+    }
+}
+
+impl Wire for u32 {
+    fn encode(&self, out: &mut WireWriter) -> Result<()> {
+        out.put(&self.to_be_bytes());
+        Ok(())
+    }
+
+    fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+        Ok(u32::from_be_bytes(input.take(4)?.try_into().unwrap()))
+    }
+}
+
+impl Wire for u64 {
+    fn encode(&self, out: &mut WireWriter) -> Result<()> {
+        out.put(&self.to_be_bytes());
+        Ok(())
+    }
+
+    fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+        Ok(u64::from_be_bytes(input.take(8)?.try_into().unwrap()))
+    }
+}
+
+impl Wire for Vec<u8> {
+    fn encode(&self, out: &mut WireWriter) -> Result<()> {
+        let len = u32::try_from(self.len()).map_err(|_| Error::Wire("too long".to_string()))?;
+        len.encode(out)?;
+        out.put(self);
+        Ok(())
+    }
+
+    fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+        let len = u32::decode(input)?;
+        Ok(input.take(len as usize)?.to_vec())
+    }
+}
+
+impl Wire for String {
+    fn encode(&self, out: &mut WireWriter) -> Result<()> {
+        self.as_bytes().to_vec().encode(out)
+    }
+
+    fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+        String::from_utf8(Vec::decode(input)?).map_err(|e| Error::Wire(e.to_string()))
+    }
+}
+
+/// Write one frame. `RemoteAddr::send` has already refused payloads over `MAX_FRAME_LEN`.
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    writer.write_u32(payload.len() as u32).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+/// The next frame, or `None` if the other end closed the connection between frames.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    // The stream ending part way through the length is as much a broken frame as any other
+    let mut len = [0; 4];
+    if reader.read(&mut len[..1]).await? == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut len[1..]).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+trait Socket: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> Socket for T {}
+
+/// Endpoint
+/// Where a remote actor can be reached.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl Endpoint {
+    async fn connect(&self) -> io::Result<Box<dyn Socket>> {
+        Ok(match self {
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// Backoff
+/// How a remote address retries a connection that cannot be made. The delay between attempts
+/// doubles from `initial` up to `max`, and the frame being sent fails after `attempts` tries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub attempts: usize,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(5),
+            attempts: 10,
+        }
+    }
+}
+
+/// RemoteAddr
+/// Sends messages to an actor served by `Server` in another process. Messages are written in
+/// order over one connection, which is opened on the first send and reopened whenever it fails.
+/// Delivery is at most once: a message written just before the connection broke may be lost.
+pub struct RemoteAddr<M> {
+    connection: Addr<Connection>,
+    _message: PhantomData<fn(M)>,
+}
+
+impl<M: Wire> RemoteAddr<M> {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self::with_backoff(endpoint, Backoff::default())
+    }
+
+    pub fn with_backoff(endpoint: Endpoint, backoff: Backoff) -> Self {
+        let connection = Connection {
+            endpoint,
+            backoff,
+            socket: None,
+        };
+        Self {
+            connection: run_actor(connection, 8),
+            _message: PhantomData,
+        }
+    }
+
+    /// Close the connection once the messages already being sent have been written.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.connection.shutdown().await
+    }
+}
+
+#[async_trait]
+impl<M: Wire + Send + 'static> ActorSender<M> for RemoteAddr<M> {
+    /// Resolves once the message has been written to the connection.
+    async fn send(&self, msg: M) -> Result<()> {
+        // Checked here so a message that can never be sent leaves the connection alone
+        let payload = to_wire(&msg)?;
+        if payload.len() > MAX_FRAME_LEN {
+            return Err(Error::Wire(format!(
+                "{} bytes is over the frame limit of {}",
+                payload.len(),
+                MAX_FRAME_LEN
+            )));
+        }
+        self.connection.ask(Frame(payload)).await
+    }
+
+    /// Only `false` once shut down, an unreachable server is retried on every send.
+    fn is_alive(&self) -> bool {
+        self.connection.is_alive()
+    }
+}
+
+impl<M> Clone for RemoteAddr<M> {
+    fn clone(&self) -> Self {
+        Self {
+            connection: self.connection.clone(),
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<M> fmt::Debug for RemoteAddr<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteAddr")
+            .field("message", &std::any::type_name::<M>())
+            .finish_non_exhaustive()
+    }
+}
+
+struct Frame(Vec<u8>);
+
+impl Message for Frame {
+    type Response = ();
+}
+
+/// The actor owning a remote address's connection
+struct Connection {
+    endpoint: Endpoint,
+    backoff: Backoff,
+    socket: Option<Box<dyn Socket>>,
+}
+
+impl Connection {
+    async fn connect(&self) -> Result<Box<dyn Socket>> {
+        let mut delay = self.backoff.initial;
+        let mut attempt = 1;
+        loop {
+            match self.endpoint.connect().await {
+                Ok(socket) => return Ok(socket),
+                Err(e) if attempt >= self.backoff.attempts => {
+                    return Err(Error::Remote(format!(
+                        "could not connect to {}: {}",
+                        self.endpoint, e
+                    )))
+                }
+                Err(_) => {
+                    sleep(delay).await;
+                    delay = (delay * 2).min(self.backoff.max);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Actor for Connection {}
+
+#[async_trait]
+impl Handler<Frame> for Connection {
+    async fn handle_message(&mut self, frame: Frame, _: &mut Context<Self>) -> Result<()> {
+        // A connection that went away since the last frame gets one fresh attempt
+        let mut failure = None;
+        for _ in 0..2 {
+            let socket = match self.socket.as_mut() {
+                Some(socket) => socket,
+                None => match self.connect().await {
+                    Ok(socket) => self.socket.insert(socket),
+                    Err(e) => return Err(e),
+                },
+            };
+            match write_frame(socket, &frame.0).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    self.socket = None;
+                    failure = Some(e);
+                }
+            }
+        }
+        let e = failure.expect("a write was attempted");
+        Err(Error::Remote(format!(
+            "could not write to {}: {}",
+            self.endpoint, e
+        )))
+    }
+}
+
+enum Acceptor {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Acceptor {
+    async fn bind(endpoint: &Endpoint) -> io::Result<(Self, Endpoint)> {
+        Ok(match endpoint {
+            Endpoint::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                let bound = Endpoint::Tcp(listener.local_addr()?);
+                (Acceptor::Tcp(listener), bound)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => (
+                Acceptor::Unix(tokio::net::UnixListener::bind(path)?),
+                endpoint.clone(),
+            ),
+        })
+    }
+
+    async fn accept(&self) -> io::Result<Box<dyn Socket>> {
+        Ok(match self {
+            Acceptor::Tcp(listener) => Box::new(listener.accept().await?.0),
+            #[cfg(unix)]
+            Acceptor::Unix(listener) => Box::new(listener.accept().await?.0),
+        })
+    }
+}
+
+/// Server
+/// Accepts connections from `RemoteAddr`s and feeds the messages they carry into a local actor.
+/// Each connection's messages arrive in the order they were sent.
+/// Dropping the server stops it.
+#[derive(Debug)]
+pub struct Server {
+    endpoint: Endpoint,
+    task: AbortHandle,
+    stopped: AtomicBool,
+}
+
+impl Server {
+    pub async fn bind<M>(endpoint: Endpoint, recipient: Recipient<M>) -> Result<Self>
+    where
+        M: Message + Wire,
+    {
+        Self::listen(endpoint, recipient, None).await
+    }
+
+    /// As `bind`, decoding messages with `context`, for example the parameters needed to read
+    /// back the keys they carry.
+    pub async fn bind_with<M, C>(
+        endpoint: Endpoint,
+        recipient: Recipient<M>,
+        context: C,
+    ) -> Result<Self>
+    where
+        M: Message + Wire,
+        C: Any + Send + Sync,
+    {
+        Self::listen(endpoint, recipient, Some(Arc::new(context))).await
+    }
+
+    async fn listen<M>(
+        endpoint: Endpoint,
+        recipient: Recipient<M>,
+        context: Option<Arc<dyn Any + Send + Sync>>,
+    ) -> Result<Self>
+    where
+        M: Message + Wire,
+    {
+        let (acceptor, endpoint) = Acceptor::bind(&endpoint)
+            .await
+            .map_err(|e| Error::Remote(format!("could not listen on {}: {}", endpoint, e)))?;
+        let task = tokio::spawn(async move {
+            // Connections are dropped along with the server
+            let mut connections = JoinSet::new();
+            loop {
+                match acceptor.accept().await {
+                    Ok(socket) => {
+                        connections.spawn(receive(socket, recipient.clone(), context.clone()));
+                    }
+                    Err(e) => {
+                        // Errors such as running out of file descriptors don't clear straight away
+                        tracing::error!(error = %e, "could not accept connection");
+                        sleep(ACCEPT_RETRY).await;
+                    }
+                }
+                while connections.try_join_next().is_some() {}
+            }
+        });
+        Ok(Self {
+            endpoint,
+            task: task.abort_handle(),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    /// Where the server is listening, with the actual port if it was bound to port 0.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Stop accepting connections and close the open ones. A Unix socket's file is removed so the
+    /// path can be bound again.
+    pub fn stop(&self) {
+        // Only the first stop cleans up, by then another server may have bound the same path
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.task.abort();
+        #[cfg(unix)]
+        if let Endpoint::Unix(path) = &self.endpoint {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn receive<M: Message + Wire>(
+    mut socket: Box<dyn Socket>,
+    recipient: Recipient<M>,
+    context: Option<Arc<dyn Any + Send + Sync>>,
+) {
+    loop {
+        let frame = match read_frame(&mut socket).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!(error = %e, "could not read from connection");
+                return;
+            }
+        };
+        match decode_frame(&frame, context.as_deref()) {
+            Ok(msg) => {
+                if recipient.send(msg).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => tracing::warn!(error = %e, "dropping message that could not be decoded"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{eventually, wait_until, Inbox};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Note(u32, String);
+
+    impl Message for Note {
+        type Response = ();
+    }
+
+    impl Wire for Note {
+        fn encode(&self, out: &mut WireWriter) -> Result<()> {
+            self.0.encode(out)?;
+            self.1.encode(out)
+        }
+
+        fn decode(input: &mut WireReader<'_>) -> Result<Self> {
+            Ok(Note(u32::decode(input)?, String::decode(input)?))
+        }
+    }
+
+    fn note(n: u32) -> Note {
+        Note(n, format!("note {}", n))
+    }
+
+    /// Serve an inbox at `endpoint` and send it three notes from a remote address
+    async fn round_trip(endpoint: Endpoint) -> Result<()> {
+        let (inbox, received) = Inbox::<Note>::run();
+        let server = Server::bind(endpoint, inbox.recipient()).await?;
+
+        let remote = RemoteAddr::new(server.endpoint().clone());
+        for n in 1..=3 {
+            remote.send(note(n)).await?;
+        }
+        remote.shutdown().await?;
+        // The server feeds the inbox once it has read the frames
+        wait_until(|| received.lock().unwrap().len() == 3).await;
+        assert_eq!(*received.lock().unwrap(), vec![note(1), note(2), note(3)]);
+        server.stop();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_loopback() -> Result<()> {
+        round_trip(Endpoint::Tcp("127.0.0.1:0".parse().unwrap())).await?;
+
+        #[cfg(unix)]
+        {
+            let path = std::env::temp_dir().join(format!("remote-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            round_trip(Endpoint::Unix(path.clone())).await?;
+            // Stopping removed the socket file so the path can be served again
+            round_trip(Endpoint::Unix(path.clone())).await?;
+            assert!(!path.exists());
+        }
+
+        assert!(matches!(
+            from_wire::<Note>(&[0, 0, 0, 1, 0, 0, 0, 9, b'x']),
+            Err(Error::Wire(_))
+        ));
+        // Only a stream that ends between frames is closed cleanly
+        assert!(read_frame(&mut &[][..]).await.unwrap().is_none());
+        let cut_short = read_frame(&mut &[0, 0][..]).await;
+        assert_eq!(cut_short.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() -> Result<()> {
+        // Find a free port for a server that is not listening yet
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let endpoint = Endpoint::Tcp(SocketAddr::from(([127, 0, 0, 1], port)));
+        let backoff = Backoff {
+            initial: Duration::from_millis(5),
+            max: Duration::from_millis(20),
+            attempts: 2,
+        };
+
+        let remote = RemoteAddr::with_backoff(endpoint.clone(), backoff);
+        assert!(matches!(remote.send(note(1)).await, Err(Error::Remote(_))));
+
+        let (inbox, received) = Inbox::run();
+        let server = Server::bind(endpoint, inbox.recipient()).await?;
+        remote.send(note(2)).await?;
+
+        // Connections are closed along with the server and reopened once it is back
+        server.stop();
+        sleep(Duration::from_millis(20)).await;
+        let server = Server::bind(server.endpoint().clone(), inbox.recipient()).await?;
+        // The first write may still land on the closed connection and be lost
+        let (remote, received) = (&remote, &received);
+        eventually(|| async move {
+            let _ = remote.send(note(3)).await;
+            received.lock().unwrap().contains(&note(3)).then_some(())
+        })
+        .await;
+        assert_eq!(received.lock().unwrap()[0], note(2));
+        server.stop();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let remote = RemoteAddr::new(Endpoint::Tcp(listener.local_addr().unwrap()));
+        remote.send(note(1)).await?;
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        let huge = Note(2, "x".repeat(MAX_FRAME_LEN));
+        assert!(matches!(remote.send(huge).await, Err(Error::Wire(_))));
+
+        // The next note goes over the same connection, a new one would never be accepted
+        remote.send(note(3)).await?;
+        for n in [1, 3] {
+            let frame = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut socket))
+                .await
+                .expect("the note arrives")
+                .unwrap()
+                .unwrap();
+            assert_eq!(from_wire::<Note>(&frame)?, note(n));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dropped_server_stops_listening() -> Result<()> {
+        let (inbox, _) = Inbox::<Note>::run();
+        let server = Server::bind(
+            Endpoint::Tcp("127.0.0.1:0".parse().unwrap()),
+            inbox.recipient(),
+        )
+        .await?;
+        let Endpoint::Tcp(addr) = server.endpoint().clone() else {
+            unreachable!()
+        };
+        drop(server);
+
+        // The listener closes once the aborted accept loop has been dropped
+        eventually(|| async { TcpStream::connect(addr).await.err() }).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use tokio::sync::Notify;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Shared>>> = const { RefCell::new(None) };
+}
+
+/// Most yields taken before an actor gets to its next message
+const MAX_YIELDS: u32 = 3;
+
+/// Yields that let newly spawned tasks get going before checking for idleness
+const SETTLE_YIELDS: u32 = 16;
+
+struct Shared {
+    /// Messages sent but not yet handled
+    pending: AtomicUsize,
+    /// Bumped whenever a message is sent or handled
+    steps: AtomicUsize,
+    handled: Notify,
+    rng: Mutex<ChaCha8Rng>,
+}
+
+fn current() -> Option<Arc<Shared>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Whether this thread is running a simulation.
+pub(crate) fn is_active() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// Held by every message sent during a simulation until it has been handled or dropped.
+pub(crate) struct Pending(Arc<Shared>);
+
+pub(crate) fn track() -> Option<Pending> {
+    let shared = current()?;
+    shared.pending.fetch_add(1, Ordering::SeqCst);
+    shared.steps.fetch_add(1, Ordering::SeqCst);
+    Some(Pending(shared))
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.pending.fetch_sub(1, Ordering::SeqCst);
+        self.0.steps.fetch_add(1, Ordering::SeqCst);
+        self.0.handled.notify_one();
+    }
+}
+
+/// Called before an actor takes its next message. In a simulation this gives way to other tasks
+/// a seeded number of times, so each seed reproducibly picks one interleaving of the actors.
+pub(crate) async fn yield_point() {
+    let Some(shared) = current() else {
+        return;
+    };
+    let yields = shared.rng.lock().unwrap().gen_range(0..=MAX_YIELDS);
+    for _ in 0..yields {
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Sim
+/// A deterministic runtime for actor tests. Time is virtual and only moves when every actor is
+/// waiting on it or when `advance` is called, blocking work runs inline, and the order actors run
+/// in is decided by the seed. Only works on a current thread runtime, which `#[tokio::test]`
+/// gives by default.
+pub struct Sim {
+    shared: Arc<Shared>,
+}
+
+impl Sim {
+    pub fn new(seed: u64) -> Self {
+        assert_eq!(
+            tokio::runtime::Handle::current().runtime_flavor(),
+            tokio::runtime::RuntimeFlavor::CurrentThread,
+            "a simulation needs a current thread runtime"
+        );
+        tokio::time::pause();
+        let shared = Arc::new(Shared {
+            pending: AtomicUsize::new(0),
+            steps: AtomicUsize::new(0),
+            handled: Notify::new(),
+            rng: Mutex::new(rand::SeedableRng::seed_from_u64(seed)),
+        });
+        CURRENT.with(|current| *current.borrow_mut() = Some(shared.clone()));
+        Self { shared }
+    }
+
+    /// Wait until every message sent so far, and every message sent while handling those, has
+    /// been handled.
+    pub async fn run_until_idle(&self) {
+        loop {
+            let steps = self.shared.steps.load(Ordering::SeqCst);
+            for _ in 0..SETTLE_YIELDS {
+                tokio::task::yield_now().await;
+            }
+            if self.shared.pending.load(Ordering::SeqCst) > 0 {
+                self.shared.handled.notified().await;
+            } else if self.shared.steps.load(Ordering::SeqCst) == steps {
+                return;
+            }
+        }
+    }
+
+    /// Run until idle, move the clock forward firing any timers that come due, and run until idle
+    /// again.
+    pub async fn advance(&self, duration: std::time::Duration) {
+        self.run_until_idle().await;
+        tokio::time::advance(duration).await;
+        self.run_until_idle().await;
+    }
+}
+
+impl Drop for Sim {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().take());
+        tokio::time::resume();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        actor_traits::{run_actor, Actor, Addr, Context, Handler, Message},
+        error::Result,
+    };
+
+    type Journal = Arc<Mutex<Vec<String>>>;
+
+    struct Relay {
+        name: &'static str,
+        next: Option<Addr<Relay>>,
+        journal: Journal,
+    }
+
+    struct Baton(u32);
+    impl Message for Baton {
+        type Response = ();
+    }
+
+    impl Actor for Relay {}
+
+    #[async_trait]
+    impl Handler<Baton> for Relay {
+        async fn handle_message(&mut self, msg: Baton, _: &mut Context<Self>) -> Result<()> {
+            self.journal
+                .lock()
+                .unwrap()
+                .push(format!("{}:{}", self.name, msg.0));
+            if let (Some(next), true) = (&self.next, msg.0 > 0) {
+                next.send(Baton(msg.0 - 1)).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Two relays passing batons on to a third, all started at once
+    async fn race(seed: u64) -> Vec<String> {
+        let sim = Sim::new(seed);
+        let journal = Journal::default();
+        let relay = |name, next| {
+            run_actor(
+                Relay {
+                    name,
+                    next,
+                    journal: journal.clone(),
+                },
+                8,
+            )
+        };
+        let last = relay("c", None);
+        let a = relay("a", Some(last.clone()));
+        let b = relay("b", Some(last));
+        for n in 1..=3 {
+            a.send(Baton(n)).await.unwrap();
+            b.send(Baton(n * 10)).await.unwrap();
+        }
+
+        sim.run_until_idle().await;
+        let journal = journal.lock().unwrap().clone();
+        journal
+    }
+
+    #[tokio::test]
+    async fn test_same_seed_same_schedule() {
+        let journal = race(7).await;
+        assert_eq!(journal.len(), 12);
+        assert_eq!(race(7).await, journal);
+
+        // The seed is what picks the interleaving, not the runtime
+        let mut schedules = HashSet::new();
+        for seed in 0..20 {
+            schedules.insert(race(seed).await);
+        }
+        assert!(schedules.len() > 1, "every seed gave the same schedule");
+    }
+
+    struct Alarm(Journal);
+
+    struct Ring;
+    impl Message for Ring {
+        type Response = ();
+    }
+
+    #[async_trait]
+    impl Actor for Alarm {
+        async fn started(&mut self, ctx: &mut Context<Self>) {
+            ctx.notify_later(Ring, Duration::from_secs(3600));
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Ring> for Alarm {
+        async fn handle_message(&mut self, _: Ring, _: &mut Context<Self>) -> Result<()> {
+            self.0.lock().unwrap().push("ring".to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_virtual_clock() {
+        let sim = Sim::new(0);
+        let journal = Journal::default();
+        let _alarm = run_actor(Alarm(journal.clone()), 8);
+
+        sim.advance(Duration::from_secs(3599)).await;
+        assert!(journal.lock().unwrap().is_empty());
+
+        sim.advance(Duration::from_secs(2)).await;
+        assert_eq!(*journal.lock().unwrap(), vec!["ring"]);
+    }
+}
@@ -1,38 +1,58 @@
 use async_trait::async_trait;
-use tokio::sync::mpsc;
 
-use crate::actor_traits::*;
-
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+use crate::{actor_traits::*, error::Result};
 
 #[derive(Debug)]
-pub enum StoreEvent {
-    Insert { key: Vec<u8>, value: Vec<u8> },
+pub struct Insert {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl Message for Insert {
+    type Response = ();
 }
 
 #[derive(Debug, Clone)]
 pub struct DataStore {
-    sender: mpsc::Sender<StoreEvent>,
+    addr: Addr<StoreActor>,
 }
 
-pub trait Store: Send + 'static {
-    fn insert(&self, key: impl Into<Vec<u8>>, data: impl Into<Vec<u8>>);
+#[async_trait]
+pub trait Store: Send + Sync + 'static {
+    /// Waits for the store to make room rather than failing when it is busy. A store that has
+    /// stopped fails with the delivery error, so it can be told apart from a failed write.
+    async fn insert(
+        &self,
+        key: impl Into<Vec<u8>> + Send,
+        data: impl Into<Vec<u8>> + Send,
+    ) -> Result<()>;
 }
 
 impl DataStore {
     pub fn new() -> Self {
         let actor = StoreActor::new();
-        let sender = run_actor(actor, 8);
-        DataStore { sender }
+        let addr = run_named("store", actor, 8);
+        DataStore { addr }
+    }
+
+    /// Stop the store once pending inserts are handled and wait for it to finish.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.addr.shutdown().await
     }
 }
+
+#[async_trait]
 impl Store for DataStore {
-    fn insert(&self, key: impl Into<Vec<u8>>, data: impl Into<Vec<u8>>) {
-        let _ = self.sender.send(StoreEvent::Insert {
+    async fn insert(
+        &self,
+        key: impl Into<Vec<u8>> + Send,
+        data: impl Into<Vec<u8>> + Send,
+    ) -> Result<()> {
+        let insert = Insert {
             key: key.into(),
             value: data.into(),
-        });
+        };
+        self.addr.send(insert).await
     }
 }
 
@@ -44,12 +64,11 @@ impl StoreActor {
     }
 }
 
+impl Actor for StoreActor {}
+
 #[async_trait]
-impl Actor<StoreEvent> for StoreActor {
-    async fn handle_message(&mut self, msg: StoreEvent) -> Result<()> {
-        match msg {
-            StoreEvent::Insert { .. } => (),
-        }
+impl Handler<Insert> for StoreActor {
+    async fn handle_message(&mut self, _: Insert, _: &mut Context<Self>) -> Result<()> {
         Ok(())
     }
 }
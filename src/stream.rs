@@ -0,0 +1,192 @@
+use std::{future::poll_fn, pin::pin};
+
+use futures_core::Stream;
+use tokio::task::AbortHandle;
+
+use crate::actor_traits::{Actor, Addr, Handler, Message};
+
+impl<A: Actor> Addr<A> {
+    /// Send every item of `stream` to this actor. Forwarding ends when the stream does or when
+    /// the actor stops accepting messages, whichever comes first. The stream does not keep the
+    /// actor running once every address to it has been dropped.
+    pub fn attach_stream<S>(&self, stream: S) -> StreamHandle
+    where
+        S: Stream + Send + 'static,
+        S::Item: Message,
+        A: Handler<S::Item>,
+    {
+        self.forward(stream, None::<S::Item>)
+    }
+
+    /// As `attach_stream` but sends `finished` to the actor once the stream has run dry. Nothing
+    /// is sent if the actor stops first.
+    pub fn attach_stream_then<S, M>(&self, stream: S, finished: M) -> StreamHandle
+    where
+        S: Stream + Send + 'static,
+        S::Item: Message,
+        A: Handler<S::Item> + Handler<M>,
+        M: Message,
+    {
+        self.forward(stream, Some(finished))
+    }
+
+    fn forward<S, M>(&self, stream: S, finished: Option<M>) -> StreamHandle
+    where
+        S: Stream + Send + 'static,
+        S::Item: Message,
+        A: Handler<S::Item> + Handler<M>,
+        M: Message,
+    {
+        // Held weakly so an endless stream doesn't keep the actor running once its addresses are
+        // gone
+        let addr = self.downgrade();
+        let task = tokio::spawn(async move {
+            let mut stream = pin!(stream);
+            loop {
+                let item = tokio::select! {
+                    biased;
+                    _ = addr.closed() => return,
+                    item = poll_fn(|cx| stream.as_mut().poll_next(cx)) => item,
+                };
+                let Some(msg) = item else {
+                    break;
+                };
+                let Some(target) = addr.upgrade() else {
+                    return;
+                };
+                if target.send(msg).await.is_err() {
+                    return;
+                }
+            }
+            if let (Some(finished), Some(target)) = (finished, addr.upgrade()) {
+                let _ = target.send(finished).await;
+            }
+        });
+        StreamHandle(task.abort_handle())
+    }
+}
+
+/// StreamHandle
+/// Returned when attaching a stream to an actor so it can be detached early.
+#[derive(Debug, Clone)]
+pub struct StreamHandle(AbortHandle);
+
+impl StreamHandle {
+    /// Stop forwarding and drop the stream. The completion message is not sent.
+    pub fn detach(&self) {
+        self.0.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        task::{Context as TaskContext, Poll},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        actor_traits::{run_actor, Context},
+        error::Result,
+    };
+
+    /// Yields `n` down to 1 and then ends, or never yields anything when `n` is `None`
+    struct Countdown(Option<u32>);
+
+    impl Stream for Countdown {
+        type Item = Tick;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<Option<Tick>> {
+            match self.0 {
+                None => Poll::Pending,
+                Some(0) => Poll::Ready(None),
+                Some(n) => {
+                    self.0 = Some(n - 1);
+                    Poll::Ready(Some(Tick(n)))
+                }
+            }
+        }
+    }
+
+    struct Tick(u32);
+    impl Message for Tick {
+        type Response = ();
+    }
+
+    struct Done;
+    impl Message for Done {
+        type Response = ();
+    }
+
+    struct Collector {
+        ticks: Vec<u32>,
+        done: mpsc::UnboundedSender<Vec<u32>>,
+    }
+
+    impl Actor for Collector {}
+
+    #[async_trait]
+    impl Handler<Tick> for Collector {
+        async fn handle_message(&mut self, msg: Tick, _: &mut Context<Self>) -> Result<()> {
+            self.ticks.push(msg.0);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Done> for Collector {
+        async fn handle_message(&mut self, _: Done, _: &mut Context<Self>) -> Result<()> {
+            let _ = self.done.send(self.ticks.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_attach_stream() -> Result<()> {
+        let (done, mut received) = mpsc::unbounded_channel();
+        let collector = run_actor(
+            Collector {
+                ticks: vec![],
+                done,
+            },
+            8,
+        );
+
+        collector.attach_stream_then(Countdown(Some(3)), Done);
+        assert_eq!(received.recv().await, Some(vec![3, 2, 1]));
+
+        // A stream that never ends is dropped along with the actor
+        let idle = collector.attach_stream(Countdown(None));
+        collector.shutdown().await?;
+        tokio::task::yield_now().await;
+        assert!(idle.is_finished());
+
+        // Nor does it keep the actor running once its address is dropped
+        let (done, mut received) = mpsc::unbounded_channel();
+        let collector = run_actor(
+            Collector {
+                ticks: vec![],
+                done,
+            },
+            8,
+        );
+        let idle = collector.attach_stream(Countdown(None));
+        drop(collector);
+        // The actor is dropped and its channel with it
+        let stopped = tokio::time::timeout(Duration::from_secs(5), received.recv()).await;
+        assert_eq!(stopped, Ok(None));
+        tokio::task::yield_now().await;
+        assert!(idle.is_finished());
+
+        Ok(())
+    }
+}
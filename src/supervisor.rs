@@ -0,0 +1,413 @@
+use std::{collections::VecDeque, fmt, sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{mpsc, Mutex},
+    time::{sleep, Instant},
+};
+
+use crate::{
+    actor_traits::{
+        drain_actor, next_message, panic_message, Actor, Addr, Context, Envelope, Lifecycle,
+        Mailbox, MailboxConfig,
+    },
+    error::Error,
+    mailbox::{mailbox, WeakMailboxSender},
+    registry::{Metrics, Registration},
+};
+
+/// Strategy
+/// What a supervisor does when its actor fails, either by returning an error from
+/// `handle_message` or by panicking.
+#[derive(Debug, Clone)]
+pub enum Strategy {
+    /// Replace the actor with a fresh one from the factory straight away
+    Restart,
+    /// Keep the actor and carry on with the next message. A failure outside a handler, such as a
+    /// panic in a lifecycle hook, restarts the actor instead, as does a handler cut short by its
+    /// deadline since it may have left the actor half way through a change.
+    Resume,
+    /// Replace the actor after a delay that doubles with every restart in the current window
+    RestartWithBackoff { initial: Duration, max: Duration },
+    /// Stop the actor and report the failure on the escalation channel
+    Escalate,
+    /// Stop the actor. Senders will see the mailbox as closed.
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureReason {
+    Error(Error),
+    Panic(String),
+    /// The handler ran past its deadline and was abandoned
+    Deadline(Duration),
+}
+
+/// Failure
+/// Reported on the escalation channel when a supervisor gives up on its actor.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub actor: &'static str,
+    pub reason: FailureReason,
+    pub restarts: usize,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reason {
+            FailureReason::Error(e) => write!(f, "{} failed: {}", self.actor, e),
+            FailureReason::Panic(p) => write!(f, "{} panicked: {}", self.actor, p),
+            FailureReason::Deadline(d) => {
+                write!(f, "{} ran past its deadline of {:?}", self.actor, d)
+            }
+        }
+    }
+}
+
+/// Supervisor
+/// Runs an actor built by `factory` and replaces it according to its `Strategy` when it fails.
+/// The mailbox outlives each incarnation so senders are unaffected by a restart. A failed
+/// incarnation is dropped without its stop hooks being run.
+pub struct Supervisor<F> {
+    factory: F,
+    name: Option<String>,
+    strategy: Strategy,
+    max_restarts: usize,
+    within: Duration,
+    escalate_to: Option<mpsc::Sender<Failure>>,
+}
+
+impl<F> Supervisor<F> {
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory,
+            name: None,
+            strategy: Strategy::Restart,
+            max_restarts: 10,
+            within: Duration::from_secs(60),
+            escalate_to: None,
+        }
+    }
+
+    /// List the actor in the registry under `name` rather than its type
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Give up once the actor has been restarted `max_restarts` times within `within`
+    pub fn max_restarts(mut self, max_restarts: usize, within: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.within = within;
+        self
+    }
+
+    /// Where failures go when the strategy is `Escalate` or the restart limit is reached
+    pub fn escalate_to(mut self, escalate_to: mpsc::Sender<Failure>) -> Self {
+        self.escalate_to = Some(escalate_to);
+        self
+    }
+
+    pub fn run<A>(mut self, config: impl Into<MailboxConfig>) -> Addr<A>
+    where
+        F: Fn() -> A + Send + 'static,
+        A: Actor,
+    {
+        let (sender, mailbox) = mailbox(config.into());
+        let lifecycle = Lifecycle::new();
+
+        // Restarts keep adding up to the same entry in the registry
+        let registration = Registration::new(self.name.take(), sender.downgrade());
+        let metrics = registration.metrics();
+        lifecycle.attach(tokio::spawn(registration.hold(self.supervise(
+            Arc::new(Mutex::new(mailbox)),
+            sender.downgrade(),
+            lifecycle.clone(),
+            metrics.clone(),
+        ))));
+
+        Addr::new(sender, lifecycle, metrics)
+    }
+
+    async fn supervise<A>(
+        self,
+        mailbox: Arc<Mutex<Mailbox<A>>>,
+        sender: WeakMailboxSender<Box<dyn Envelope<A>>>,
+        lifecycle: Lifecycle,
+        metrics: Arc<Metrics>,
+    ) where
+        F: Fn() -> A + Send + 'static,
+        A: Actor,
+    {
+        let actor_name = std::any::type_name::<A>();
+        let mut restarts: VecDeque<Instant> = VecDeque::new();
+
+        let resume = matches!(self.strategy, Strategy::Resume);
+
+        loop {
+            let child = tokio::spawn(consume_until_failure(
+                (self.factory)(),
+                mailbox.clone(),
+                Context::new(sender.clone(), lifecycle.clone(), metrics.clone()),
+                resume,
+            ));
+            let reason = match child.await {
+                Ok(None) => return,
+                Ok(Some(reason)) => reason,
+                Err(err) if err.is_panic() => FailureReason::Panic(panic_message(err.into_panic())),
+                Err(_) => return,
+            };
+
+            let now = Instant::now();
+            while restarts
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > self.within)
+            {
+                restarts.pop_front();
+            }
+
+            let failure = Failure {
+                actor: actor_name,
+                reason,
+                restarts: restarts.len(),
+            };
+            tracing::warn!(restarts = failure.restarts, "supervisor: {}", failure);
+
+            let delay = match &self.strategy {
+                _ if restarts.len() >= self.max_restarts => {
+                    return escalate(&self.escalate_to, failure).await
+                }
+                Strategy::Restart | Strategy::Resume => Duration::ZERO,
+                Strategy::RestartWithBackoff { initial, max } => initial
+                    .saturating_mul(2u32.saturating_pow(restarts.len() as u32))
+                    .min(*max),
+                Strategy::Escalate => return escalate(&self.escalate_to, failure).await,
+                Strategy::Stop => return,
+            };
+
+            sleep(delay).await;
+            restarts.push_back(Instant::now());
+        }
+    }
+}
+
+async fn escalate(escalate_to: &Option<mpsc::Sender<Failure>>, failure: Failure) {
+    if let Some(escalate_to) = escalate_to {
+        let _ = escalate_to.send(failure).await;
+    }
+}
+
+/// Process messages until the actor stops, returning the first handler failure if there is one
+/// unless failures are to be resumed. The context goes with the failed incarnation, taking its
+/// timers and children with it.
+async fn consume_until_failure<A: Actor>(
+    mut actor: A,
+    mailbox: Arc<Mutex<Mailbox<A>>>,
+    mut ctx: Context<A>,
+    resume: bool,
+) -> Option<FailureReason> {
+    let mut mailbox = mailbox.lock().await;
+    actor.started(&mut ctx).await;
+    while let Some(envelope) = next_message(&mut mailbox, &ctx).await {
+        match envelope.handle(&mut actor, &mut ctx).await {
+            Ok(()) => (),
+            // Already reported in the handler's span, the actor carries on
+            Err(reason) if resume && !matches!(reason, FailureReason::Deadline(_)) => (),
+            Err(reason) => return Some(reason),
+        }
+    }
+    drain_actor(&mut actor, &mut mailbox, &mut ctx).await;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        actor_traits::{Handler, Message},
+        error::Result,
+        sim::Sim,
+    };
+
+    enum CounterMessage {
+        Increment,
+        Explode,
+        Stall,
+        Get,
+    }
+
+    impl Message for CounterMessage {
+        type Response = u32;
+
+        fn deadline(&self) -> Option<Duration> {
+            match self {
+                CounterMessage::Stall => Some(Duration::from_millis(10)),
+                _ => None,
+            }
+        }
+    }
+
+    struct Counter(u32);
+
+    impl Actor for Counter {}
+
+    #[async_trait]
+    impl Handler<CounterMessage> for Counter {
+        async fn handle_message(
+            &mut self,
+            msg: CounterMessage,
+            _: &mut Context<Self>,
+        ) -> Result<u32> {
+            match msg {
+                CounterMessage::Increment => self.0 += 1,
+                CounterMessage::Explode => panic!("boom"),
+                CounterMessage::Stall => sleep(Duration::from_secs(60)).await,
+                CounterMessage::Get => (),
+            }
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restart_then_escalate() -> Result<()> {
+        let (escalate_to, mut failures) = mpsc::channel(1);
+        let counter = Supervisor::new(|| Counter(0))
+            .max_restarts(1, Duration::from_secs(60))
+            .escalate_to(escalate_to)
+            .run(8);
+
+        counter.send(CounterMessage::Increment).await?;
+        counter.send(CounterMessage::Explode).await?;
+        // Restarted with fresh state and the same mailbox
+        assert_eq!(counter.ask(CounterMessage::Get).await?, 0);
+
+        counter.send(CounterMessage::Explode).await?;
+        let failure = failures.recv().await.unwrap();
+        assert_eq!(failure.reason, FailureReason::Panic("boom".to_string()));
+        assert!(counter.send(CounterMessage::Increment).await.is_err());
+
+        Ok(())
+    }
+
+    /// Answers with how many times it has been built, and panics while starting the first time
+    struct Fragile(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Actor for Fragile {
+        async fn started(&mut self, _: &mut Context<Self>) {
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("bad start");
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Handler<CounterMessage> for Fragile {
+        async fn handle_message(
+            &mut self,
+            _: CounterMessage,
+            _: &mut Context<Self>,
+        ) -> Result<u32> {
+            Ok(self.0.load(Ordering::SeqCst) as u32)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume() -> Result<()> {
+        let _sim = Sim::new(0);
+        let counter = Supervisor::new(|| Counter(0))
+            .strategy(Strategy::Resume)
+            .run(8);
+
+        counter.send(CounterMessage::Increment).await?;
+        counter.send(CounterMessage::Explode).await?;
+        // The same actor carries on with its state
+        assert_eq!(counter.ask(CounterMessage::Get).await?, 1);
+
+        // Unless a handler was cut short by its deadline
+        let stalled = counter.ask(CounterMessage::Stall).await;
+        assert_eq!(
+            stalled,
+            Err(Error::DeadlineExceeded(Duration::from_millis(10)))
+        );
+        assert_eq!(counter.ask(CounterMessage::Get).await?, 0);
+
+        // Or it failed outside a handler
+        let builds = Arc::new(AtomicUsize::new(0));
+        let factory_builds = builds.clone();
+        let fragile = Supervisor::new(move || Fragile(factory_builds.clone()))
+            .strategy(Strategy::Resume)
+            .run(8);
+        assert_eq!(fragile.ask(CounterMessage::Get).await?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restart_with_backoff() -> Result<()> {
+        let _sim = Sim::new(0);
+        let built = Arc::new(std::sync::Mutex::new(vec![]));
+        let factory_built = built.clone();
+        let counter = Supervisor::new(move || {
+            factory_built.lock().unwrap().push(Instant::now());
+            Counter(0)
+        })
+        .strategy(Strategy::RestartWithBackoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(25),
+        })
+        .run(8);
+
+        for _ in 0..3 {
+            counter.send(CounterMessage::Explode).await?;
+            counter.ask(CounterMessage::Get).await?;
+        }
+
+        let built = built.lock().unwrap();
+        let delays = built
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        // The delay doubles with every restart up to the cap, give or take the timer's resolution
+        let millis = delays.iter().map(Duration::as_millis).collect::<Vec<_>>();
+        assert!(
+            millis
+                .iter()
+                .zip([10, 20, 25])
+                .all(|(ms, expected)| (expected..=expected + 1).contains(ms)),
+            "{:?}",
+            millis
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stop() -> Result<()> {
+        let _sim = Sim::new(0);
+        let (escalate_to, mut failures) = mpsc::channel(1);
+        let counter = Supervisor::new(|| Counter(0))
+            .strategy(Strategy::Stop)
+            .escalate_to(escalate_to)
+            .run(8);
+
+        counter.send(CounterMessage::Explode).await?;
+        counter.join().await?;
+        assert_eq!(
+            counter.send(CounterMessage::Increment).await,
+            Err(Error::MailboxClosed)
+        );
+        // Stopping is not escalated
+        assert!(failures.recv().await.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    future::{ready, Future},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    actor_traits::{run_actor, Actor, Addr, Context, Handler, Message},
+    error::Result,
+};
+
+/// How long a test waits for something to happen before failing
+const PATIENCE: Duration = Duration::from_secs(5);
+
+/// Poll `check` every millisecond until it gives a value, failing the test if that takes too long.
+pub async fn eventually<T, F>(mut check: impl FnMut() -> F) -> T
+where
+    F: Future<Output = Option<T>>,
+{
+    let polling = async {
+        loop {
+            if let Some(value) = check().await {
+                return value;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    };
+    tokio::time::timeout(PATIENCE, polling)
+        .await
+        .unwrap_or_else(|_| panic!("still waiting after {:?}", PATIENCE))
+}
+
+/// Wait for `condition` to hold, failing the test if that takes too long.
+pub async fn wait_until(condition: impl Fn() -> bool) {
+    eventually(|| ready(condition().then_some(()))).await
+}
+
+/// Messages an inbox has received, shared with the test that reads them
+pub type Received<M> = Arc<Mutex<Vec<M>>>;
+
+/// Inbox
+/// An actor that keeps every message it is sent, in the order they arrive.
+pub struct Inbox<M>(Received<M>);
+
+impl<M: Send + 'static> Inbox<M> {
+    /// Run an inbox, returning its address and the messages it has received so far.
+    pub fn run() -> (Addr<Self>, Received<M>) {
+        let received = Received::default();
+        (run_actor(Inbox(received.clone()), 8), received)
+    }
+}
+
+impl<M: Send + 'static> Actor for Inbox<M> {}
+
+#[async_trait]
+impl<M: Message<Response = ()>> Handler<M> for Inbox<M> {
+    async fn handle_message(&mut self, msg: M, _: &mut Context<Self>) -> Result<()> {
+        self.0.lock().unwrap().push(msg);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    fmt,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tracing::Span;
+
+tokio::task_local! {
+    static CURRENT: Trace;
+}
+
+/// MessageId
+/// Identifies a single message sent to an actor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId(u64);
+
+impl MessageId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Trace
+/// Where a message sits in the chain of messages that led to it. A message sent while another is
+/// being handled shares that message's correlation id and names it as its cause, anything else
+/// starts a new chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trace {
+    /// Unique to this message
+    pub id: MessageId,
+    /// Shared by every message following from the same first message, whose id it is
+    pub correlation_id: MessageId,
+    /// The message that was being handled when this one was sent
+    pub causation_id: Option<MessageId>,
+}
+
+impl Trace {
+    /// The trace of a message being sent from the current task.
+    pub(crate) fn new() -> Self {
+        let id = MessageId::next();
+        match current() {
+            Some(cause) => Self {
+                id,
+                correlation_id: cause.correlation_id,
+                causation_id: Some(cause.id),
+            },
+            None => Self {
+                id,
+                correlation_id: id,
+                causation_id: None,
+            },
+        }
+    }
+
+    /// The span a handler runs in while handling this message.
+    pub(crate) fn span(&self, actor: &'static str, message: &'static str) -> Span {
+        tracing::info_span!(
+            "handle",
+            actor,
+            message,
+            id = self.id.0,
+            correlation_id = self.correlation_id.0,
+            causation_id = self.causation_id.map(|id| id.0),
+        )
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message {} (correlation {}",
+            self.id, self.correlation_id
+        )?;
+        if let Some(cause) = self.causation_id {
+            write!(f, ", caused by {}", cause)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The trace of the message the current task is handling, if any.
+pub fn current() -> Option<Trace> {
+    CURRENT.try_with(|trace| *trace).ok()
+}
+
+/// Run `future` as the handling of the message traced by `trace`, so whatever it sends is
+/// recorded as caused by that message.
+pub(crate) async fn scope<F: Future>(trace: Trace, future: F) -> F::Output {
+    CURRENT.scope(trace, future).await
+}
+
+/// Carry the current trace over to `future`, for work that is spawned off on behalf of the
+/// message being handled.
+pub(crate) fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let trace = current();
+    async move {
+        match trace {
+            Some(trace) => scope(trace, future).await,
+            None => future.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        actor_traits::{run_actor, Actor, Addr, Context, Handler, Message},
+        error::Result,
+        sim::Sim,
+    };
+
+    type Seen = Arc<Mutex<Vec<Option<Trace>>>>;
+
+    /// Records the trace of everything it handles and passes it on to `next`
+    struct Hop {
+        next: Option<Addr<Hop>>,
+        seen: Seen,
+    }
+
+    struct Ping;
+    impl Message for Ping {
+        type Response = ();
+    }
+
+    struct Later;
+    impl Message for Later {
+        type Response = ();
+    }
+
+    impl Actor for Hop {}
+
+    #[async_trait]
+    impl Handler<Ping> for Hop {
+        async fn handle_message(&mut self, _: Ping, ctx: &mut Context<Self>) -> Result<()> {
+            self.seen.lock().unwrap().push(current());
+            match &self.next {
+                Some(next) => next.send(Ping).await?,
+                None => {
+                    ctx.notify_later(Later, Duration::from_millis(1));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Handler<Later> for Hop {
+        async fn handle_message(&mut self, _: Later, _: &mut Context<Self>) -> Result<()> {
+            self.seen.lock().unwrap().push(current());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trace_follows_messages() {
+        let sim = Sim::new(0);
+        let seen = Seen::default();
+        let last = run_actor(
+            Hop {
+                next: None,
+                seen: seen.clone(),
+            },
+            8,
+        );
+        let first = run_actor(
+            Hop {
+                next: Some(last.clone()),
+                seen: seen.clone(),
+            },
+            8,
+        );
+        assert_eq!(current(), None);
+
+        first.send(Ping).await.unwrap();
+        sim.advance(Duration::from_secs(1)).await;
+        let seen: Vec<Trace> = seen.lock().unwrap().iter().map(|t| t.unwrap()).collect();
+        assert_eq!(seen.len(), 3);
+
+        let [ping, forwarded, later] = seen[..] else {
+            unreachable!()
+        };
+        assert_eq!(ping.correlation_id, ping.id);
+        assert_eq!(ping.causation_id, None);
+        assert_eq!(forwarded.correlation_id, ping.id);
+        assert_eq!(forwarded.causation_id, Some(ping.id));
+        assert_eq!(later.correlation_id, ping.id);
+        assert_eq!(later.causation_id, Some(forwarded.id));
+    }
+}